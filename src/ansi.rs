@@ -0,0 +1,472 @@
+use crate::markdown::push_prefixed;
+use crate::parser::{Document, Node, NodeKind};
+use crate::tokenizer::Alignment;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+const BULLETS: [&str; 3] = ["•", "◦", "▪"];
+const HEADING_COLORS: [u8; 6] = [35, 34, 36, 32, 33, 31];
+
+/// How the document is laid out on a terminal.
+pub struct Options {
+    /// Columns to wrap text at.
+    pub width: usize,
+    /// Styles text with escape codes, which is only wanted on a terminal.
+    pub color: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            width: 80,
+            color: true,
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+struct Style {
+    bold: bool,
+    dim: bool,
+    italic: bool,
+    underline: bool,
+    strikethrough: bool,
+    color: Option<u8>,
+}
+
+impl Style {
+    // The SGR parameters that turn the style on.
+    fn codes(&self) -> String {
+        let attributes = [
+            (self.bold, 1),
+            (self.dim, 2),
+            (self.italic, 3),
+            (self.underline, 4),
+            (self.strikethrough, 9),
+        ];
+        attributes
+            .into_iter()
+            .filter_map(|(on, code)| on.then_some(code))
+            .chain(self.color)
+            .map(|code| code.to_string())
+            .collect::<Vec<_>>()
+            .join(";")
+    }
+}
+
+// Text along with the style it's shown in.
+type Segment = (String, Style);
+
+/// Renders the document for a terminal, wrapping text at `options.width`.
+pub fn render(document: &Document, options: &Options) -> String {
+    render_blocks(&document.children, options.width, 0, false, options)
+}
+
+// Blocks are separated by a blank line, unless they're in a list item.
+fn render_blocks(
+    nodes: &[Node],
+    width: usize,
+    lists: usize,
+    tight: bool,
+    options: &Options,
+) -> String {
+    let mut text = String::new();
+    for (i, node) in nodes.iter().enumerate() {
+        if i > 0 && !tight {
+            text.push('\n');
+        }
+        render_block(node, width, lists, options, &mut text);
+    }
+    text
+}
+
+// `lists` is how deeply the block is nested in lists, which picks the bullet.
+fn render_block(node: &Node, width: usize, lists: usize, options: &Options, text: &mut String) {
+    match &node.kind {
+        NodeKind::Heading { level, children } => {
+            let style = Style {
+                bold: true,
+                color: Some(HEADING_COLORS[(*level as usize).clamp(1, 6) - 1]),
+                ..Style::default()
+            };
+            let mut segments = vec![];
+            collect_segments(children, style, &mut segments);
+            push_lines(wrap(&segments, width, options), text);
+        }
+        NodeKind::Paragraph { children } => {
+            let mut segments = vec![];
+            collect_segments(children, Style::default(), &mut segments);
+            push_lines(wrap(&segments, width, options), text);
+        }
+        NodeKind::List {
+            start,
+            tight,
+            children,
+        } => {
+            let markers: Vec<String> = (0..children.len())
+                .map(|i| match start {
+                    Some(start) => format!("{}.", *start as usize + i),
+                    None => BULLETS[lists % BULLETS.len()].to_string(),
+                })
+                .collect();
+            let marker_width = markers.iter().map(|m| m.width()).max();
+            let marker_width = marker_width.unwrap_or_default();
+            let indent = " ".repeat(marker_width + 1);
+            for (i, (marker, item)) in markers.iter().zip(children).enumerate() {
+                if i > 0 && !tight {
+                    text.push('\n');
+                }
+                let marker = format!("{marker:>marker_width$} ");
+                let NodeKind::ListItem { children } = &item.kind else {
+                    continue;
+                };
+                let width = width.saturating_sub(marker_width + 1);
+                let item = render_blocks(children, width, lists + 1, *tight, options);
+                if item.is_empty() {
+                    push_lines(vec![marker.trim_end().to_string()], text);
+                    continue;
+                }
+                push_prefixed(&item, &marker, &indent, str::to_string, text);
+            }
+        }
+        NodeKind::Blockquote { children } => {
+            let quote = render_blocks(children, width.saturating_sub(2), lists, false, options);
+            let style = Style {
+                dim: true,
+                ..Style::default()
+            };
+            let bar = |prefix: &str| paint(prefix, style, options);
+            push_prefixed(&quote, "│ ", "│ ", bar, text);
+        }
+        NodeKind::CodeBlock { lang, text: code } => {
+            render_code_block(lang, code, width, options, text)
+        }
+        NodeKind::HorizontalRule => push_lines(vec!["─".repeat(width)], text),
+        NodeKind::Table {
+            alignments,
+            children,
+        } => render_table(alignments, children, options, text),
+        _ => {
+            let mut segments = vec![];
+            collect_segments(std::slice::from_ref(node), Style::default(), &mut segments);
+            push_lines(wrap(&segments, width, options), text);
+        }
+    }
+}
+
+fn push_lines(lines: Vec<String>, text: &mut String) {
+    for line in lines {
+        text.push_str(&line);
+        text.push('\n');
+    }
+}
+
+// Code isn't wrapped at spaces but cut wherever it reaches the box's edge.
+fn render_code_block(lang: &str, code: &str, width: usize, options: &Options, text: &mut String) {
+    let border = Style {
+        dim: true,
+        ..Style::default()
+    };
+    let inner = width.saturating_sub(4).max(1);
+    let label = if lang.is_empty() {
+        String::new()
+    } else {
+        format!(" {lang} ")
+    };
+    let fill = (inner + 2).saturating_sub(label.width() + 1);
+    let top = format!("┌─{label}{}┐", "─".repeat(fill));
+    text.push_str(&paint(&top, border, options));
+    text.push('\n');
+
+    for line in code.lines() {
+        // Lines are cut where the next character would no longer fit.
+        let mut chunks = vec![(String::new(), 0)];
+        for c in line.replace('\t', "    ").chars() {
+            let c_width = c.width().unwrap_or_default();
+            let (chunk, chunk_width) = chunks.last_mut().unwrap();
+            if *chunk_width > 0 && *chunk_width + c_width > inner {
+                chunks.push((c.to_string(), c_width));
+            } else {
+                chunk.push(c);
+                *chunk_width += c_width;
+            }
+        }
+        for (chunk, chunk_width) in chunks {
+            text.push_str(&paint("│ ", border, options));
+            text.push_str(&chunk);
+            text.push_str(&" ".repeat(inner.saturating_sub(chunk_width)));
+            text.push_str(&paint(" │", border, options));
+            text.push('\n');
+        }
+    }
+
+    let bottom = format!("└{}┘", "─".repeat(inner + 2));
+    text.push_str(&paint(&bottom, border, options));
+    text.push('\n');
+}
+
+// Columns are as wide as their widest cell, tables aren't wrapped.
+fn render_table(alignments: &[Alignment], rows: &[Node], options: &Options, text: &mut String) {
+    let rows: Vec<Vec<(String, usize)>> = rows
+        .iter()
+        .enumerate()
+        .map(|(i, row)| {
+            let NodeKind::TableRow { children } = &row.kind else {
+                return vec![];
+            };
+            let style = Style {
+                bold: i == 0,
+                ..Style::default()
+            };
+            children
+                .iter()
+                .map(|cell| {
+                    let mut segments = vec![];
+                    if let NodeKind::TableCell { children } = &cell.kind {
+                        collect_segments(children, style, &mut segments);
+                    }
+                    let width = segments.iter().map(|(s, _)| s.width()).sum();
+                    let cell = segments
+                        .iter()
+                        .map(|(s, style)| paint(s, *style, options))
+                        .collect();
+                    (cell, width)
+                })
+                .collect()
+        })
+        .collect();
+    let mut widths = vec![0; alignments.len()];
+    for row in &rows {
+        for (width, (_, cell)) in widths.iter_mut().zip(row) {
+            *width = (*width).max(*cell);
+        }
+    }
+
+    for (i, row) in rows.iter().enumerate() {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(alignments.iter().zip(&widths))
+            .map(|((cell, width), (alignment, column))| {
+                let padding = column - width;
+                let (left, right) = match alignment {
+                    Alignment::Right => (padding, 0),
+                    Alignment::Center => (padding / 2, padding - padding / 2),
+                    _ => (0, padding),
+                };
+                format!("{}{cell}{}", " ".repeat(left), " ".repeat(right))
+            })
+            .collect();
+        text.push_str(cells.join(" │ ").trim_end());
+        text.push('\n');
+        if i == 0 {
+            let rules: Vec<String> = widths.iter().map(|width| "─".repeat(*width)).collect();
+            text.push_str(&rules.join("─┼─"));
+            text.push('\n');
+        }
+    }
+}
+
+fn collect_segments(nodes: &[Node], style: Style, segments: &mut Vec<Segment>) {
+    for node in nodes {
+        match &node.kind {
+            NodeKind::Emphasis { children } => {
+                let style = Style {
+                    italic: true,
+                    ..style
+                };
+                collect_segments(children, style, segments);
+            }
+            NodeKind::Strong { children } => {
+                let style = Style {
+                    bold: true,
+                    ..style
+                };
+                collect_segments(children, style, segments);
+            }
+            NodeKind::Strikethrough { children } => {
+                let style = Style {
+                    strikethrough: true,
+                    ..style
+                };
+                collect_segments(children, style, segments);
+            }
+            NodeKind::Link {
+                destination: target,
+                children,
+                ..
+            }
+            | NodeKind::Image {
+                source: target,
+                children,
+                ..
+            } => {
+                let link = Style {
+                    underline: true,
+                    color: Some(34),
+                    ..style
+                };
+                collect_segments(children, link, segments);
+                // The target is only worth repeating when the text differs.
+                let text: String = children
+                    .iter()
+                    .filter_map(|child| match &child.kind {
+                        NodeKind::Text(text) => Some(text.as_str()),
+                        _ => None,
+                    })
+                    .collect();
+                if text != *target {
+                    let dim = Style { dim: true, ..style };
+                    segments.push((format!(" ({target})"), dim));
+                }
+            }
+            NodeKind::CodeSpan(code) => {
+                let style = Style {
+                    color: Some(36),
+                    ..style
+                };
+                segments.push((code.clone(), style));
+            }
+            NodeKind::SoftBreak => segments.push((" ".to_string(), style)),
+            NodeKind::Text(text) => segments.push((text.clone(), style)),
+            _ => {}
+        }
+    }
+}
+
+// Breaks the text into lines of at most `width` columns at whitespace. A word
+// longer than that gets a line of its own.
+fn wrap(segments: &[Segment], width: usize, options: &Options) -> Vec<String> {
+    let mut lines = vec![];
+    let mut line = String::new();
+    let mut line_width = 0;
+    let mut word: Vec<Segment> = vec![];
+    let mut word_width = 0;
+
+    let mut push_word = |word: &mut Vec<Segment>, word_width: &mut usize| {
+        if word.is_empty() {
+            return;
+        }
+        if line_width > 0 && line_width + 1 + *word_width > width {
+            lines.push(std::mem::take(&mut line));
+            line_width = 0;
+        }
+        if line_width > 0 {
+            line.push(' ');
+            line_width += 1;
+        }
+        for (text, style) in word.drain(..) {
+            line.push_str(&paint(&text, style, options));
+        }
+        line_width += *word_width;
+        *word_width = 0;
+    };
+
+    for (text, style) in segments {
+        for c in text.chars() {
+            if c.is_whitespace() {
+                push_word(&mut word, &mut word_width);
+                continue;
+            }
+            match word.last_mut() {
+                Some((fragment, last)) if last == style => fragment.push(c),
+                _ => word.push((c.to_string(), *style)),
+            }
+            word_width += c.width().unwrap_or_default();
+        }
+    }
+    push_word(&mut word, &mut word_width);
+
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+fn paint(text: &str, style: Style, options: &Options) -> String {
+    if !options.color || style == Style::default() {
+        return text.to_string();
+    }
+    format!("\x1b[{}m{text}\x1b[0m", style.codes())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::parse;
+
+    fn assert_plain(input: &str, width: usize, expected_text: &str) {
+        let options = Options {
+            width,
+            color: false,
+        };
+        assert_eq!(render(&parse(input), &options), expected_text);
+    }
+
+    #[test]
+    fn styles() {
+        let input = "# a\n\n**b** _c_ ~~d~~ ~~**_e_**~~";
+        let expected_text = "\x1b[1;35ma\x1b[0m\n\n\x1b[1mb\x1b[0m \x1b[3mc\x1b[0m \x1b[9md\x1b[0m \x1b[1;3;9me\x1b[0m\n";
+        assert_eq!(render(&parse(input), &Options::default()), expected_text);
+    }
+
+    #[test]
+    fn plain_text() {
+        let input = "## a\n\nb **c** [d](e) [f](f) `g`";
+        assert_plain(input, 80, "a\n\nb c d (e) f g\n");
+    }
+
+    #[test]
+    fn wrapping() {
+        let input = "aaa bbb\nccc **dd ee** ffffffffff g";
+        assert_plain(input, 7, "aaa bbb\nccc dd\nee\nffffffffff\ng\n");
+        // Wide characters take up two columns each.
+        assert_plain("漢字 漢字 a", 6, "漢字\n漢字 a\n");
+    }
+
+    #[test]
+    fn heading_colors() {
+        let mut colors = HEADING_COLORS.to_vec();
+        colors.sort();
+        colors.dedup();
+        assert_eq!(colors.len(), HEADING_COLORS.len());
+    }
+
+    #[test]
+    fn lists() {
+        let input = "- a b c\n  - d\n    - e\n      - f\n\n9. g\n10. h";
+        let expected_text = "• a b\n  c\n  ◦ d\n    ▪ e\n      • f\n\n 9. g\n10. h\n";
+        assert_plain(input, 5, expected_text);
+    }
+
+    #[test]
+    fn blockquotes() {
+        let input = "> a b\n>\n>> c";
+        assert_plain(input, 5, "│ a b\n│\n│ │ c\n");
+        let expected_text = "\x1b[2m│ \x1b[0ma\n\x1b[2m│\x1b[0m\n\x1b[2m│ \x1b[0mb\n";
+        assert_eq!(
+            render(&parse("> a\n>\n> b"), &Options::default()),
+            expected_text
+        );
+    }
+
+    #[test]
+    fn rules_and_code_blocks() {
+        let input = "---\n\n```rust\nfn a() {}\n\n```";
+        let expected_text =
+            "──────────\n\n┌─ rust ─┐\n│ fn a() │\n│  {}    │\n│        │\n└────────┘\n";
+        assert_plain(input, 10, expected_text);
+        let expected_text = "┌────────┐\n│ 漢字漢 │\n│ 字     │\n└────────┘\n";
+        assert_plain("```\n漢字漢字\n```", 10, expected_text);
+    }
+
+    #[test]
+    fn tables() {
+        let input = "| a | bbb | c |\n|:-:|--:|---|\n| dddd | e | `f` |";
+        let expected_text = " a   │ bbb │ c\n─────┼─────┼──\ndddd │   e │ f\n";
+        assert_plain(input, 80, expected_text);
+        assert_plain(
+            "| 漢字 | a |\n|-|-|\n| b | c |",
+            80,
+            "漢字 │ a\n─────┼──\nb    │ c\n",
+        );
+    }
+}
@@ -0,0 +1,368 @@
+use crate::parser::{Document, Node, NodeKind};
+use crate::tokenizer::Alignment;
+
+/// Renders the document as HTML.
+pub fn render(document: &Document) -> String {
+    let mut html = String::new();
+    for node in &document.children {
+        render_block(node, &mut html);
+    }
+    html
+}
+
+fn render_block(node: &Node, html: &mut String) {
+    match &node.kind {
+        NodeKind::Heading { level, children } => {
+            html.push_str(&format!("<h{level}>"));
+            render_inlines(children, html);
+            html.push_str(&format!("</h{level}>\n"));
+        }
+        NodeKind::Paragraph { children } => {
+            html.push_str("<p>");
+            render_inlines(children, html);
+            html.push_str("</p>\n");
+        }
+        NodeKind::List {
+            start,
+            tight,
+            children,
+        } => {
+            let tag = if start.is_some() { "ol" } else { "ul" };
+            match start {
+                Some(start) if *start != 1 => {
+                    html.push_str(&format!("<ol start=\"{start}\">\n"));
+                }
+                _ => html.push_str(&format!("<{tag}>\n")),
+            }
+            for item in children {
+                render_item(item, *tight, html);
+            }
+            html.push_str(&format!("</{tag}>\n"));
+        }
+        NodeKind::Blockquote { children } => {
+            html.push_str("<blockquote>\n");
+            for child in children {
+                render_block(child, html);
+            }
+            html.push_str("</blockquote>\n");
+        }
+        NodeKind::CodeBlock { lang, text } => {
+            // The language is the first word of the info string.
+            match lang.split_whitespace().next() {
+                Some(lang) => {
+                    html.push_str(&format!("<pre><code class=\"language-{}\">", escape(lang)));
+                }
+                None => html.push_str("<pre><code>"),
+            }
+            html.push_str(&escape(text));
+            html.push_str("</code></pre>\n");
+        }
+        NodeKind::HorizontalRule => html.push_str("<hr />\n"),
+        NodeKind::Table {
+            alignments,
+            children,
+        } => {
+            html.push_str("<table>\n");
+            for (i, row) in children.iter().enumerate() {
+                match i {
+                    0 => html.push_str("<thead>\n"),
+                    1 => html.push_str("<tbody>\n"),
+                    _ => {}
+                }
+                render_row(row, alignments, if i == 0 { "th" } else { "td" }, html);
+                if i == 0 {
+                    html.push_str("</thead>\n");
+                }
+            }
+            if children.len() > 1 {
+                html.push_str("</tbody>\n");
+            }
+            html.push_str("</table>\n");
+        }
+        _ => render_inlines(std::slice::from_ref(node), html),
+    }
+}
+
+// The paragraphs of a tight list's items aren't wrapped in `<p>`.
+fn render_item(item: &Node, tight: bool, html: &mut String) {
+    let NodeKind::ListItem { children } = &item.kind else {
+        return;
+    };
+
+    html.push_str("<li>");
+    for child in children {
+        match &child.kind {
+            NodeKind::Paragraph { children } if tight => render_inlines(children, html),
+            _ => {
+                if !html.ends_with('\n') {
+                    html.push('\n');
+                }
+                render_block(child, html);
+            }
+        }
+    }
+    html.push_str("</li>\n");
+}
+
+fn render_row(row: &Node, alignments: &[Alignment], tag: &str, html: &mut String) {
+    let NodeKind::TableRow { children } = &row.kind else {
+        return;
+    };
+
+    html.push_str("<tr>\n");
+    for (cell, alignment) in children.iter().zip(alignments) {
+        let align = match alignment {
+            Alignment::None => "",
+            Alignment::Left => " align=\"left\"",
+            Alignment::Center => " align=\"center\"",
+            Alignment::Right => " align=\"right\"",
+        };
+        html.push_str(&format!("<{tag}{align}>"));
+        if let NodeKind::TableCell { children } = &cell.kind {
+            render_inlines(children, html);
+        }
+        html.push_str(&format!("</{tag}>\n"));
+    }
+    html.push_str("</tr>\n");
+}
+
+fn render_inlines(nodes: &[Node], html: &mut String) {
+    for node in nodes {
+        match &node.kind {
+            NodeKind::Emphasis { children } => render_tag("em", children, html),
+            NodeKind::Strong { children } => render_tag("strong", children, html),
+            NodeKind::Strikethrough { children } => render_tag("del", children, html),
+            NodeKind::Link {
+                destination,
+                title,
+                children,
+            } => {
+                html.push_str(&format!("<a href=\"{}\"", escape(destination)));
+                render_title(title, html);
+                html.push('>');
+                render_inlines(children, html);
+                html.push_str("</a>");
+            }
+            NodeKind::Image {
+                source,
+                title,
+                children,
+            } => {
+                html.push_str(&format!("<img src=\"{}\" alt=\"", escape(source)));
+                render_alt(children, html);
+                html.push('"');
+                render_title(title, html);
+                html.push_str(" />");
+            }
+            NodeKind::CodeSpan(code) => {
+                html.push_str("<code>");
+                html.push_str(&escape(code));
+                html.push_str("</code>");
+            }
+            NodeKind::SoftBreak => html.push('\n'),
+            NodeKind::Text(text) => html.push_str(&escape(text)),
+            NodeKind::ListItem { .. } | NodeKind::TableRow { .. } | NodeKind::TableCell { .. } => {
+                // Only rendered by their list or table, as render_block would
+                // hand them right back.
+            }
+            _ => render_block(node, html),
+        }
+    }
+}
+
+fn render_tag(tag: &str, children: &[Node], html: &mut String) {
+    html.push_str(&format!("<{tag}>"));
+    render_inlines(children, html);
+    html.push_str(&format!("</{tag}>"));
+}
+
+fn render_title(title: &Option<String>, html: &mut String) {
+    if let Some(title) = title {
+        html.push_str(&format!(" title=\"{}\"", escape(title)));
+    }
+}
+
+fn render_alt(nodes: &[Node], html: &mut String) {
+    for node in nodes {
+        match &node.kind {
+            NodeKind::Text(text) | NodeKind::CodeSpan(text) => html.push_str(&escape(text)),
+            NodeKind::SoftBreak => html.push(' '),
+            NodeKind::Emphasis { children }
+            | NodeKind::Strong { children }
+            | NodeKind::Strikethrough { children }
+            | NodeKind::Link { children, .. }
+            | NodeKind::Image { children, .. } => render_alt(children, html),
+            _ => {}
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::parse;
+
+    fn assert_html(input: &str, expected_html: &str) {
+        assert_eq!(render(&parse(input)), expected_html);
+    }
+
+    #[test]
+    fn headers() {
+        for level in 1..=6 {
+            let input = format!("{} Hello World", "#".repeat(level));
+            let expected_html = format!("<h{level}>Hello World</h{level}>\n");
+            assert_html(&input, &expected_html);
+        }
+    }
+
+    #[test]
+    fn text_modifiers() {
+        let input = "a **b** _c_ ~~d~~ ~~**_e_**~~";
+        let expected_html =
+            "<p>a <strong>b</strong> <em>c</em> <del>d</del> <del><strong><em>e</em></strong></del></p>\n";
+        assert_html(input, expected_html);
+    }
+
+    #[test]
+    fn special_characters() {
+        let input = "Special characters: & < > \" '";
+        let expected_html = "<p>Special characters: &amp; &lt; &gt; &quot; '</p>\n";
+        assert_html(input, expected_html);
+        // A no-break space isn't blank.
+        assert_html("\u{a0}\n\na\u{a0}", "<p>\u{a0}</p>\n<p>a\u{a0}</p>\n");
+    }
+
+    #[test]
+    fn list_and_rule() {
+        let input = "- Hello\n- **World**\n\n---";
+        let expected_html =
+            "<ul>\n<li>Hello</li>\n<li><strong>World</strong></li>\n</ul>\n<hr />\n";
+        assert_html(input, expected_html);
+    }
+
+    #[test]
+    fn code_block() {
+        let input = "```rust\nif a < b {}\n```\n```\nplain\n```";
+        let expected_html = "<pre><code class=\"language-rust\">if a &lt; b {}\n</code></pre>\n<pre><code>plain\n</code></pre>\n";
+        assert_html(input, expected_html);
+        let expected_html = "<pre><code class=\"language-rust\"></code></pre>\n";
+        assert_html("```rust ignore\n```", expected_html);
+    }
+
+    #[test]
+    fn misplaced_nodes() {
+        let node = |kind| Node {
+            kind,
+            span: Default::default(),
+        };
+        let children = vec![
+            node(NodeKind::ListItem { children: vec![] }),
+            node(NodeKind::TableRow { children: vec![] }),
+            node(NodeKind::TableCell { children: vec![] }),
+            node(NodeKind::Text("a".to_string())),
+        ];
+        assert_eq!(render(&Document { children }), "a");
+    }
+
+    #[test]
+    fn links_and_images() {
+        let input = "__[pica](https://nodeca.github.io/pica/demo/)__ [a](/b?c&d \"e\") ![**Minion**](minion.png)";
+        let expected_html = "<p><strong><a href=\"https://nodeca.github.io/pica/demo/\">pica</a></strong> <a href=\"/b?c&amp;d\" title=\"e\">a</a> <img src=\"minion.png\" alt=\"Minion\" /></p>\n";
+        assert_html(input, expected_html);
+    }
+
+    #[test]
+    fn ordered_lists() {
+        let input = "1. Lorem\n2. Ipsum\n\n57) foo\n1) bar";
+        let expected_html = "<ol>\n<li>Lorem</li>\n<li>Ipsum</li>\n</ol>\n<ol start=\"57\">\n<li>foo</li>\n<li>bar</li>\n</ol>\n";
+        assert_html(input, expected_html);
+    }
+
+    #[test]
+    fn nested_lists() {
+        let input = "+ a\n  - b\n    * c\n    + d\n+ e";
+        let expected_html = "<ul>\n<li>a\n<ul>\n<li>b\n<ul>\n<li>c</li>\n</ul>\n<ul>\n<li>d</li>\n</ul>\n</li>\n</ul>\n</li>\n<li>e</li>\n</ul>\n";
+        assert_html(input, expected_html);
+    }
+
+    #[test]
+    fn list_item_content_column() {
+        assert_html(
+            "007. a\n\n   b",
+            "<ol start=\"7\">\n<li>a</li>\n</ol>\n<p>b</p>\n",
+        );
+        assert_html("-    a\n\n  b", "<ul>\n<li>a</li>\n</ul>\n<p>b</p>\n");
+        assert_html(
+            "-     a\n\n  b",
+            "<ul>\n<li>\n<p>a</p>\n<p>b</p>\n</li>\n</ul>\n",
+        );
+        assert_html("-\n  a", "<ul>\n<li>a</li>\n</ul>\n");
+    }
+
+    #[test]
+    fn blocks_after_list_marker() {
+        let item = |html| format!("<ul>\n<li>\n{html}</li>\n</ul>\n");
+        assert_html("- # a", &item("<h1>a</h1>\n"));
+        assert_html("- > b", &item("<blockquote>\n<p>b</p>\n</blockquote>\n"));
+        assert_html("- - a", &item("<ul>\n<li>a</li>\n</ul>\n"));
+        assert_html("- ```\n  a", &item("<pre><code>a\n</code></pre>\n"));
+    }
+
+    #[test]
+    fn code_block_ends_with_list_item() {
+        let input = "- a\n  ```\n  b\nc\n  ```";
+        let expected_html = "<ul>\n<li>a\n<pre><code>b\n</code></pre>\n</li>\n</ul>\n<p>c</p>\n<pre><code></code></pre>\n";
+        assert_html(input, expected_html);
+    }
+
+    #[test]
+    fn indented_paragraph_continuation() {
+        assert_html("foo\n    # bar", "<p>foo\n# bar</p>\n");
+        let expected_html = "<blockquote>\n<p>foo\n- bar</p>\n</blockquote>\n";
+        assert_html("> foo\n    - bar", expected_html);
+        assert_html("a\n    ```\nb", "<p>a\n```\nb</p>\n");
+    }
+
+    #[test]
+    fn multi_line_list_item() {
+        let input = "- __[pica](https://nodeca.github.io/pica/demo/)__ - high quality and fast image\n  resize in browser.";
+        let expected_html = "<ul>\n<li><strong><a href=\"https://nodeca.github.io/pica/demo/\">pica</a></strong> - high quality and fast image\nresize in browser.</li>\n</ul>\n";
+        assert_html(input, expected_html);
+    }
+
+    #[test]
+    fn blockquotes() {
+        let input = "> a\n>> b\n> > > c\n>\n> # d\n> - e\n>   ```\n>   f\n>   ```";
+        let expected_html = "<blockquote>\n<p>a</p>\n<blockquote>\n<p>b</p>\n<blockquote>\n<p>c</p>\n</blockquote>\n</blockquote>\n<h1>d</h1>\n<ul>\n<li>e\n<pre><code>f\n</code></pre>\n</li>\n</ul>\n</blockquote>\n";
+        assert_html(input, expected_html);
+    }
+
+    #[test]
+    fn code_spans() {
+        let input = "Inline `code` with `` <`> `` and `**`";
+        let expected_html =
+            "<p>Inline <code>code</code> with <code>&lt;`&gt;</code> and <code>**</code></p>\n";
+        assert_html(input, expected_html);
+    }
+
+    #[test]
+    fn tables() {
+        let input = "| Option | Description |\n| ------:| -----------:|\n| data   | path to `a\\|b` |\n\n| a |\n| :- |";
+        let expected_html = "<table>\n<thead>\n<tr>\n<th align=\"right\">Option</th>\n<th align=\"right\">Description</th>\n</tr>\n</thead>\n<tbody>\n<tr>\n<td align=\"right\">data</td>\n<td align=\"right\">path to <code>a|b</code></td>\n</tr>\n</tbody>\n</table>\n<table>\n<thead>\n<tr>\n<th align=\"left\">a</th>\n</tr>\n</thead>\n</table>\n";
+        assert_html(input, expected_html);
+    }
+}
@@ -0,0 +1,106 @@
+use crate::parser::Document;
+use crate::span::Span;
+use crate::tokenizer::{tokens, Token};
+
+/// Renders the document tree as JSON, nodes and spans included.
+pub fn render(document: &Document) -> String {
+    // The tree only has string keys, so serializing it can't fail.
+    let mut json = serde_json::to_string_pretty(document).expect("serializable document");
+    json.push('\n');
+    json
+}
+
+/// Renders the tokens of `input` as JSON, one line per token and its span.
+pub fn render_tokens(input: &str) -> String {
+    let mut json = String::new();
+    #[derive(serde::Serialize)]
+    struct Line<'a> {
+        token: Token<'a>,
+        span: Span,
+    }
+
+    for (token, span) in tokens(input).spanned() {
+        let line = serde_json::to_string(&Line { token, span }).expect("serializable token");
+        json.push_str(&line);
+        json.push('\n');
+    }
+    json
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::parse;
+
+    #[test]
+    fn document() {
+        let json = render(&parse("# a"));
+        let expected_json = r#"{
+  "children": [
+    {
+      "kind": {
+        "Heading": {
+          "level": 1,
+          "children": [
+            {
+              "kind": {
+                "Text": "a"
+              },
+              "span": {
+                "start": {
+                  "line": 1,
+                  "column": 3,
+                  "offset": 2
+                },
+                "end": {
+                  "line": 1,
+                  "column": 4,
+                  "offset": 3
+                }
+              }
+            }
+          ]
+        }
+      },
+      "span": {
+        "start": {
+          "line": 1,
+          "column": 1,
+          "offset": 0
+        },
+        "end": {
+          "line": 1,
+          "column": 4,
+          "offset": 3
+        }
+      }
+    }
+  ]
+}
+"#;
+        assert_eq!(json, expected_json);
+    }
+
+    #[test]
+    fn token_lines() {
+        let expected_json = concat!(
+            r#"{"token":{"Header":1},"span":{"start":{"line":1,"column":1,"offset":0},"end":{"line":1,"column":3,"offset":2}}}"#,
+            "\n",
+            r#"{"token":{"Literal":"a"},"span":{"start":{"line":1,"column":3,"offset":2},"end":{"line":1,"column":4,"offset":3}}}"#,
+            "\n",
+        );
+        assert_eq!(render_tokens("# a"), expected_json);
+    }
+
+    #[test]
+    fn round_trip() {
+        let input = "> - **a** [b](c 'd')\n\n| e |\n|:-:|\n\n```rust\nf\n```\n";
+        let document = parse(input);
+        let json = render(&document);
+        assert_eq!(serde_json::from_str::<Document>(&json).unwrap(), document);
+
+        let tokens: Vec<Token> = tokens(input).collect();
+        let json = serde_json::to_string(&tokens).unwrap();
+        assert_eq!(serde_json::from_str::<Vec<Token>>(&json).unwrap(), tokens);
+    }
+}
@@ -0,0 +1,21 @@
+//! A Markdown parser: a line-based [`Tokenizer`], a [`Parser`] that builds a
+//! [`Document`] tree from its tokens, and renderers for that tree.
+//!
+//! ```
+//! let document = md_parser::parse("# Hello");
+//! assert_eq!(md_parser::html::render(&document), "<h1>Hello</h1>\n");
+//! ```
+
+pub mod ansi;
+pub mod html;
+#[cfg(feature = "serde")]
+pub mod json;
+pub mod markdown;
+pub mod parser;
+pub mod span;
+pub mod text;
+pub mod tokenizer;
+
+pub use parser::{parse, Document, Node, NodeKind, Parser};
+pub use span::{Position, Span};
+pub use tokenizer::{tokens, Alignment, Token, Tokenizer, Tokens};
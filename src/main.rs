@@ -1,30 +1,269 @@
-mod tokenizer;
-use std::{
-    fs::File,
-    io::{BufRead, BufReader},
-    path::Path,
-    vec,
-};
-use tokenizer::Tokenizer;
-
-fn main() {
-    let arg = std::env::args().last().unwrap();
-    let path = Path::new(&arg);
-    let file = File::open(path).unwrap();
-    let reader = BufReader::new(file);
-    let mut tokenizer = Tokenizer::new();
-
-    for line in reader.lines() {
-        match line {
-            Ok(line) => tokenizer.set_line(&line),
-            Err(_) => continue,
+use std::io::{self, IsTerminal, Read, Write};
+use std::process::ExitCode;
+use std::{env, fs};
+
+const USAGE: &str =
+    "Usage: md-parser render [--to ansi|html|json|json-tokens|markdown|text|tokens] [--width N] [FILE|-]
+
+Renders FILE, or standard input when FILE is `-` or missing, as HTML by default.
+The ansi format wraps at N columns, or $COLUMNS, and is only styled on a terminal.
+The json and json-tokens formats need the `serde` feature.";
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Format {
+    Ansi,
+    Html,
+    #[cfg(feature = "serde")]
+    Json,
+    #[cfg(feature = "serde")]
+    JsonTokens,
+    Markdown,
+    Text,
+    Tokens,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    format: Format,
+    width: Option<usize>,
+    path: Option<String>,
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Err(message) => {
+            eprintln!("md-parser: {message}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        // Output piped into e.g. `head` may be closed early.
+        Err(error) if error.kind() == io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("md-parser: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// Returns `None` when help was asked for.
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Option<Args>, String> {
+    let mut args = args.into_iter();
+    match args.next().as_deref() {
+        Some("render") => {}
+        Some("-h" | "--help") => return Ok(None),
+        Some(command) => return Err(format!("unknown command `{command}`")),
+        None => return Err("missing command".to_string()),
+    }
+
+    let mut format = Format::Html;
+    let mut width = None;
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        let value = match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--to" => args.next().ok_or("missing value for `--to`")?,
+            "--width" => {
+                let value = args.next().ok_or("missing value for `--width`")?;
+                width = Some(parse_width(&value)?);
+                continue;
+            }
+            arg if arg.starts_with("--width=") => {
+                width = Some(parse_width(&arg["--width=".len()..])?);
+                continue;
+            }
+            arg if arg.starts_with("--to=") => arg["--to=".len()..].to_string(),
+            arg if arg.starts_with('-') && arg != "-" => {
+                return Err(format!("unknown option `{arg}`"));
+            }
+            _ if path.is_some() => return Err(format!("unexpected argument `{arg}`")),
+            _ => {
+                path = Some(arg).filter(|path| path != "-");
+                continue;
+            }
+        };
+        format = match value.as_str() {
+            "ansi" => Format::Ansi,
+            "html" => Format::Html,
+            #[cfg(feature = "serde")]
+            "json" => Format::Json,
+            #[cfg(feature = "serde")]
+            "json-tokens" => Format::JsonTokens,
+            #[cfg(not(feature = "serde"))]
+            format @ ("json" | "json-tokens") => {
+                return Err(format!("format `{format}` needs the `serde` feature"));
+            }
+            "markdown" => Format::Markdown,
+            "text" => Format::Text,
+            "tokens" => Format::Tokens,
+            format => return Err(format!("unsupported format `{format}`")),
         };
+    }
+
+    Ok(Some(Args {
+        format,
+        width,
+        path,
+    }))
+}
 
-        let mut tokens = vec![];
-        while let Some(token) = tokenizer.next() {
-            tokens.push(token);
-            // println!("{:?}", token);
+fn parse_width(value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|width| *width > 0)
+        .ok_or_else(|| format!("invalid width `{value}`"))
+}
+
+fn run(args: &Args) -> io::Result<()> {
+    let input = match &args.path {
+        Some(path) => fs::read_to_string(path)
+            .map_err(|error| io::Error::new(error.kind(), format!("{path}: {error}")))?,
+        None => {
+            let mut input = String::new();
+            io::stdin().read_to_string(&mut input)?;
+            input
+        }
+    };
+
+    let mut stdout = io::stdout().lock();
+    match args.format {
+        Format::Ansi => {
+            let columns = env::var("COLUMNS")
+                .ok()
+                .and_then(|value| value.parse().ok());
+            let options = md_parser::ansi::Options {
+                width: args.width.or(columns).unwrap_or(80),
+                color: io::stdout().is_terminal(),
+            };
+            let document = md_parser::parse(&input);
+            stdout.write_all(md_parser::ansi::render(&document, &options).as_bytes())?;
+        }
+        Format::Html => {
+            let document = md_parser::parse(&input);
+            stdout.write_all(md_parser::html::render(&document).as_bytes())?;
+        }
+        #[cfg(feature = "serde")]
+        Format::Json => {
+            let document = md_parser::parse(&input);
+            stdout.write_all(md_parser::json::render(&document).as_bytes())?;
+        }
+        #[cfg(feature = "serde")]
+        Format::JsonTokens => {
+            stdout.write_all(md_parser::json::render_tokens(&input).as_bytes())?;
+        }
+        Format::Markdown => {
+            let document = md_parser::parse(&input);
+            stdout.write_all(md_parser::markdown::render(&document).as_bytes())?;
+        }
+        Format::Text => {
+            let document = md_parser::parse(&input);
+            let options = md_parser::text::Options::default();
+            stdout.write_all(md_parser::text::render(&document, &options).as_bytes())?;
+        }
+        Format::Tokens => {
+            for (token, span) in md_parser::tokens(&input).spanned() {
+                let (start, end) = (span.start, span.end);
+                writeln!(
+                    stdout,
+                    "{}:{}-{}:{} {token:?}",
+                    start.line, start.column, end.line, end.column
+                )?;
+            }
         }
-        print!("{:?}\n", tokens);
+    }
+    stdout.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Args>, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn render_args() {
+        let args = |format, path: Option<&str>| {
+            Ok(Some(Args {
+                format,
+                width: None,
+                path: path.map(str::to_string),
+            }))
+        };
+        assert_eq!(parse(&["render"]), args(Format::Html, None));
+        assert_eq!(parse(&["render", "-"]), args(Format::Html, None));
+        assert_eq!(
+            parse(&["render", "--to", "tokens", "a.md"]),
+            args(Format::Tokens, Some("a.md"))
+        );
+        assert_eq!(
+            parse(&["render", "a.md", "--to=html"]),
+            args(Format::Html, Some("a.md"))
+        );
+        assert_eq!(
+            parse(&["render", "--to", "markdown"]),
+            args(Format::Markdown, None)
+        );
+        assert_eq!(
+            parse(&["render", "--to=text", "-"]),
+            args(Format::Text, None)
+        );
+        #[cfg(feature = "serde")]
+        assert_eq!(parse(&["render", "--to", "json"]), args(Format::Json, None));
+        #[cfg(feature = "serde")]
+        assert_eq!(
+            parse(&["render", "--to=json-tokens"]),
+            args(Format::JsonTokens, None)
+        );
+        assert_eq!(
+            parse(&["render", "--to=ansi", "--width", "60"]),
+            Ok(Some(Args {
+                format: Format::Ansi,
+                width: Some(60),
+                path: None,
+            }))
+        );
+        assert_eq!(parse(&["--help"]), Ok(None));
+        assert_eq!(parse(&["render", "-h"]), Ok(None));
+    }
+
+    #[test]
+    fn invalid_args() {
+        let error = |message: &str| Err(message.to_string());
+        assert_eq!(parse(&[]), error("missing command"));
+        assert_eq!(parse(&["a.md"]), error("unknown command `a.md`"));
+        assert_eq!(
+            parse(&["render", "--to"]),
+            error("missing value for `--to`")
+        );
+        assert_eq!(
+            parse(&["render", "--to", "pdf"]),
+            error("unsupported format `pdf`")
+        );
+        #[cfg(not(feature = "serde"))]
+        assert_eq!(
+            parse(&["render", "--to", "json"]),
+            error("format `json` needs the `serde` feature")
+        );
+        #[cfg(not(feature = "serde"))]
+        assert_eq!(
+            parse(&["render", "--to", "json-tokens"]),
+            error("format `json-tokens` needs the `serde` feature")
+        );
+        assert_eq!(parse(&["render", "--width=0"]), error("invalid width `0`"));
+        assert_eq!(parse(&["render", "-x"]), error("unknown option `-x`"));
+        assert_eq!(
+            parse(&["render", "a.md", "b.md"]),
+            error("unexpected argument `b.md`")
+        );
     }
 }
@@ -0,0 +1,500 @@
+use crate::parser::{Document, Node, NodeKind};
+use crate::tokenizer::Alignment;
+
+/// Renders the document back to Markdown in a canonical style, so that
+/// rendering the parsed output again gives the same text.
+pub fn render(document: &Document) -> String {
+    render_blocks(&document.children, true, false)
+}
+
+// Blocks are separated by a blank line, except inside of the items of a tight
+// list, which a blank line would make loose.
+fn render_blocks(nodes: &[Node], blank_lines: bool, item: bool) -> String {
+    let mut markdown = String::new();
+    let mut previous_list = None;
+    for (i, node) in nodes.iter().enumerate() {
+        if i > 0 && blank_lines {
+            markdown.push('\n');
+        }
+        let kind = list_kind(node);
+        // In a list item a `---` rule would be read as the underline of the
+        // paragraph above it, or as a rule instead of the list marker.
+        let alternate = match node.kind {
+            NodeKind::HorizontalRule => item,
+            _ => kind.is_some() && kind == previous_list,
+        };
+        previous_list = kind.filter(|_| !alternate);
+        render_block(node, alternate, &mut markdown);
+    }
+    markdown
+}
+
+// Two lists right after each other only stay apart when their markers differ.
+fn list_kind(node: &Node) -> Option<bool> {
+    match &node.kind {
+        NodeKind::List { start, .. } => Some(start.is_some()),
+        _ => None,
+    }
+}
+
+fn render_block(node: &Node, alternate: bool, markdown: &mut String) {
+    match &node.kind {
+        NodeKind::Heading { level, children } => {
+            let mut text = String::new();
+            render_inlines(children, &mut text);
+            // Only an underlined heading can span lines.
+            if text.contains('\n') {
+                markdown.push_str(&text);
+                markdown.push_str(if *level == 1 { "\n===\n" } else { "\n---\n" });
+                return;
+            }
+            markdown.push_str(&"#".repeat(*level as usize));
+            // Rendered after the marker, the text only escapes what it must there.
+            if !text.is_empty() {
+                markdown.push(' ');
+                render_inlines(children, markdown);
+            }
+            markdown.push('\n');
+        }
+        NodeKind::Paragraph { children } => {
+            render_inlines(children, markdown);
+            markdown.push('\n');
+        }
+        NodeKind::List {
+            start,
+            tight,
+            children,
+        } => {
+            for (i, item) in children.iter().enumerate() {
+                if i > 0 && !tight {
+                    markdown.push('\n');
+                }
+                let marker = match (start, alternate) {
+                    (Some(start), false) => format!("{}.", *start as usize + i),
+                    (Some(start), true) => format!("{})", *start as usize + i),
+                    (None, false) => "-".to_string(),
+                    (None, true) => "*".to_string(),
+                };
+                let NodeKind::ListItem { children } = &item.kind else {
+                    continue;
+                };
+                let item = render_blocks(children, !tight, true);
+                if item.is_empty() {
+                    markdown.push_str(&marker);
+                    markdown.push('\n');
+                    continue;
+                }
+                let indent = " ".repeat(marker.len() + 1);
+                // Only text and rules are read on the line of the marker, so
+                // other blocks start on the line below it.
+                let own_line = match children[0].kind {
+                    NodeKind::Paragraph { .. } | NodeKind::HorizontalRule => false,
+                    NodeKind::Heading { .. } => item.starts_with('#'),
+                    _ => true,
+                };
+                if own_line {
+                    markdown.push_str(&marker);
+                    markdown.push('\n');
+                    push_prefixed(&item, &indent, &indent, str::to_string, markdown);
+                    continue;
+                }
+                push_prefixed(
+                    &item,
+                    &format!("{marker} "),
+                    &indent,
+                    str::to_string,
+                    markdown,
+                );
+            }
+        }
+        NodeKind::Blockquote { children } => {
+            let quote = render_blocks(children, true, false);
+            if quote.is_empty() {
+                markdown.push_str(">\n");
+            }
+            push_prefixed(&quote, "> ", "> ", str::to_string, markdown);
+        }
+        NodeKind::CodeBlock { lang, text } => {
+            markdown.push_str("```");
+            push_info(lang, markdown);
+            markdown.push('\n');
+            markdown.push_str(text);
+            markdown.push_str("```\n");
+        }
+        NodeKind::HorizontalRule if alternate => markdown.push_str("___\n"),
+        NodeKind::HorizontalRule => markdown.push_str("---\n"),
+        NodeKind::Table {
+            alignments,
+            children,
+        } => {
+            for (i, row) in children.iter().enumerate() {
+                if let NodeKind::TableRow { children } = &row.kind {
+                    let cells = children.iter().map(|cell| {
+                        let mut text = String::new();
+                        if let NodeKind::TableCell { children } = &cell.kind {
+                            render_inlines(children, &mut text);
+                        }
+                        escape_pipes(&text)
+                    });
+                    push_row(cells, markdown);
+                }
+                if i == 0 {
+                    let delimiters = alignments.iter().map(|alignment| match alignment {
+                        Alignment::None => "---",
+                        Alignment::Left => ":--",
+                        Alignment::Center => ":-:",
+                        Alignment::Right => "--:",
+                    });
+                    push_row(delimiters, markdown);
+                }
+            }
+        }
+        _ => {
+            render_inlines(std::slice::from_ref(node), markdown);
+            markdown.push('\n');
+        }
+    }
+}
+
+// Prefixes the first line of `text` with `first` and the others with `rest`,
+// leaving no trailing whitespace on blank lines. Prefixes are trimmed before
+// `paint` styles them, which the ANSI renderer does.
+pub(crate) fn push_prefixed(
+    text: &str,
+    first: &str,
+    rest: &str,
+    paint: impl Fn(&str) -> String,
+    out: &mut String,
+) {
+    for (i, line) in text.lines().enumerate() {
+        let prefix = if i == 0 { first } else { rest };
+        if line.is_empty() {
+            out.push_str(&paint(prefix.trim_end()));
+        } else {
+            out.push_str(&paint(prefix));
+            out.push_str(line);
+        }
+        out.push('\n');
+    }
+}
+
+fn push_row<S: AsRef<str>>(cells: impl Iterator<Item = S>, markdown: &mut String) {
+    markdown.push('|');
+    for cell in cells {
+        markdown.push(' ');
+        markdown.push_str(cell.as_ref());
+        markdown.push_str(" |");
+    }
+    markdown.push('\n');
+}
+
+fn render_inlines(nodes: &[Node], markdown: &mut String) {
+    for (i, node) in nodes.iter().enumerate() {
+        match &node.kind {
+            NodeKind::Emphasis { children } => {
+                // `_` can't open or close inside of a word, and `__` would be
+                // read as a strong marker.
+                let intraword = markdown.ends_with(|c: char| c.is_alphanumeric() || c == '_')
+                    || nodes.get(i + 1).is_some_and(starts_word);
+                let marker = if intraword { "*" } else { "_" };
+                render_delimited(marker, children, markdown);
+            }
+            NodeKind::Strong { children } => {
+                // Next to another `*` run, `**` would join it, so `__` takes its
+                // place where it can open and close.
+                let joins = markdown.ends_with('*') || nodes.get(i + 1).is_some_and(is_delimited);
+                let intraword = markdown.ends_with(|c: char| c.is_alphanumeric() || c == '_')
+                    || nodes.get(i + 1).is_some_and(starts_word);
+                let marker = if joins && !intraword { "__" } else { "**" };
+                render_delimited(marker, children, markdown);
+            }
+            NodeKind::Strikethrough { children } => render_delimited("~~", children, markdown),
+            NodeKind::Link {
+                destination,
+                title,
+                children,
+            } => {
+                markdown.push('[');
+                render_inlines(children, markdown);
+                markdown.push(']');
+                render_target(destination, title, markdown);
+            }
+            NodeKind::Image {
+                source,
+                title,
+                children,
+            } => {
+                markdown.push_str("![");
+                render_inlines(children, markdown);
+                markdown.push(']');
+                render_target(source, title, markdown);
+            }
+            NodeKind::CodeSpan(code) => render_code_span(code, markdown),
+            NodeKind::SoftBreak => markdown.push('\n'),
+            NodeKind::Text(text) => push_text(text, nodes.get(i + 1), markdown),
+            _ => {}
+        }
+    }
+}
+
+// Escapes the punctuation that could be read as markup, which at the start of
+// a line includes the markers of blocks.
+fn push_text(text: &str, next: Option<&Node>, markdown: &mut String) {
+    let line_start = markdown.is_empty() || markdown.ends_with('\n');
+    let digits = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let following = chars.peek().map(|(_, c)| *c);
+        let escape = match c {
+            '`' | '*' | '[' | ']' | '~' | '|' => true,
+            '\\' => following.is_none_or(|c| c.is_ascii_punctuation()),
+            // `_` inside of a word can't open or close emphasis.
+            '_' => {
+                !(markdown.ends_with(char::is_alphanumeric)
+                    && following.is_some_and(char::is_alphanumeric))
+            }
+            '!' => following.is_none() && next.is_some_and(is_link),
+            '#' | '>' | '-' | '+' | '=' => line_start && i == 0,
+            '.' | ')' => line_start && i == digits && (1..=9).contains(&digits),
+            _ => false,
+        };
+        if escape {
+            markdown.push('\\');
+        }
+        markdown.push(c);
+    }
+}
+
+// The info string is unescaped, so its backslashes are escaped again.
+fn push_info(lang: &str, markdown: &mut String) {
+    let mut chars = lang.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' && chars.peek().is_none_or(|c| c.is_ascii_punctuation()) {
+            markdown.push('\\');
+        }
+        markdown.push(c);
+    }
+}
+
+fn is_delimited(node: &Node) -> bool {
+    matches!(
+        node.kind,
+        NodeKind::Emphasis { .. } | NodeKind::Strong { .. }
+    )
+}
+
+fn is_link(node: &Node) -> bool {
+    matches!(node.kind, NodeKind::Link { .. })
+}
+
+// Pipes in text are escaped already, but the ones in code spans aren't.
+fn escape_pipes(cell: &str) -> String {
+    let mut escaped = String::new();
+    let mut backslashes = 0usize;
+    for c in cell.chars() {
+        if c == '|' && backslashes.is_multiple_of(2) {
+            escaped.push('\\');
+        }
+        backslashes = if c == '\\' { backslashes + 1 } else { 0 };
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn starts_word(node: &Node) -> bool {
+    matches!(&node.kind, NodeKind::Text(text) if text.starts_with(char::is_alphanumeric))
+}
+
+fn render_delimited(marker: &str, children: &[Node], markdown: &mut String) {
+    markdown.push_str(marker);
+    render_inlines(children, markdown);
+    markdown.push_str(marker);
+}
+
+fn render_target(destination: &str, title: &Option<String>, markdown: &mut String) {
+    markdown.push('(');
+    if destination.contains(char::is_whitespace) {
+        markdown.push_str(&format!("<{destination}>"));
+    } else {
+        markdown.push_str(destination);
+    }
+    if let Some(title) = title {
+        // A title can't contain its closing quote.
+        let (open, close) = match (title.contains('"'), title.contains('\'')) {
+            (false, _) => ('"', '"'),
+            (true, false) => ('\'', '\''),
+            (true, true) => ('(', ')'),
+        };
+        markdown.push_str(&format!(" {open}{title}{close}"));
+    }
+    markdown.push(')');
+}
+
+// The backtick run has to be longer than any run inside of the code.
+fn render_code_span(code: &str, markdown: &mut String) {
+    let longest = code
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or_default();
+    let fence = "`".repeat(longest + 1);
+    let padded = code.starts_with('`')
+        || code.ends_with('`')
+        || (code.len() > 1
+            && code.starts_with(' ')
+            && code.ends_with(' ')
+            && code.bytes().any(|b| b != b' '));
+    let padding = if padded { " " } else { "" };
+    markdown.push_str(&format!("{fence}{padding}{code}{padding}{fence}"));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::html;
+    use crate::parser::parse;
+
+    fn assert_markdown(input: &str, expected_markdown: &str) {
+        let markdown = render(&parse(input));
+        assert_eq!(markdown, expected_markdown);
+    }
+
+    // Formatting twice gives the same text as formatting once, and the text
+    // reads the same as the input, for any line built from these pieces and
+    // any two of them, with or without a blank line in between.
+    #[test]
+    fn idempotent() {
+        let prefixes = ["", "#  ", "> ", "- ", "2) ", "   "];
+        let texts = [
+            "a",
+            "> quote",
+            "-a",
+            "` `` `",
+            "\\#",
+            "   ",
+            "===",
+            "---",
+            "*a*",
+            "[a](b c)",
+            "```",
+            "1.",
+            "| a |",
+            "|-|",
+            "\u{a0}",
+            "__a__**b**",
+            "__(__**- - -**",
+            "__a__*b*c",
+            "```a\\+b",
+        ];
+        let lines: Vec<String> = prefixes
+            .iter()
+            .flat_map(|prefix| texts.iter().map(move |text| format!("{prefix}{text}")))
+            .collect();
+        for first in &lines {
+            for second in &lines {
+                for separator in ["\n", "\n\n"] {
+                    let input = format!("{first}{separator}{second}");
+                    let document = parse(&input);
+                    let markdown = render(&document);
+                    assert_eq!(render(&parse(&markdown)), markdown, "{input:?}");
+                    let html = html::render(&document);
+                    assert_eq!(html::render(&parse(&markdown)), html, "{input:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn headers() {
+        assert_markdown("#   a\n###### b ##", "# a\n\n###### b ##\n");
+        assert_markdown("#  > quote\n##  -a\n#    ", "# > quote\n\n## -a\n\n#\n");
+    }
+
+    #[test]
+    fn setext_headers() {
+        let input = "a\nb\n===\n\nc\n---\n\n- d\n  e\n  ---\n- f\n  ***\n* - - -\n\ng\n\\=";
+        let expected_markdown =
+            "a\nb\n===\n\n## c\n\n- d\n  e\n  ---\n- f\n  ___\n\n* ___\n\ng\n\\=\n";
+        assert_markdown(input, expected_markdown);
+    }
+
+    #[test]
+    fn paragraphs() {
+        let input = "a\nb   \n  lazy\n\n\n\nc";
+        assert_markdown(input, "a\nb\nlazy\n\nc\n");
+    }
+
+    #[test]
+    fn text_modifiers() {
+        let input = "*a* __b__ ~~c~~ *__d__* _a_*b* **x a*b*c";
+        let expected_markdown = "_a_ **b** ~~c~~ _**d**_ _a_*b* \\*\\*x a*b*c\n";
+        assert_markdown(input, expected_markdown);
+        // Runs next to each other can't share a marker character.
+        assert_markdown("__a__**b**__c__ **d**_e_", "__a__**b**__c__ __d__*e*\n");
+    }
+
+    #[test]
+    fn escapes() {
+        let input = "\\# a\\_b \\*c\\* snake_case \\[d](e) \\![f](g) \\a\\\\\n\\- h\n1\\. i\n2) j";
+        let expected_markdown =
+            "\\# a_b \\*c\\* snake_case \\[d\\](e) \\![f](g) \\a\\\\\n\\- h\n1\\. i\n2\\) j\n";
+        assert_markdown(input, expected_markdown);
+    }
+
+    #[test]
+    fn lists() {
+        let input = "+ a\n* b\n\n57. c\n1. d\n- e\n    * f\n  1) g\n- ";
+        let expected_markdown = "- a\n\n* b\n\n57. c\n58. d\n\n- e\n  - f\n  1. g\n-\n";
+        assert_markdown(input, expected_markdown);
+        let input = "1. a\n\n   b\n2. c\n   - d\n\n   - e\n\n\nf";
+        let expected_markdown = "1. a\n\n   b\n\n2. c\n\n   - d\n\n   - e\n\nf\n";
+        assert_markdown(input, expected_markdown);
+    }
+
+    #[test]
+    fn adjacent_lists() {
+        let input = "- a\n  + b\n  * c\n  1. d\n  1) e";
+        let expected_markdown = "- a\n  - b\n  * c\n  1. d\n  1) e\n";
+        assert_markdown(input, expected_markdown);
+    }
+
+    #[test]
+    fn blockquotes() {
+        let input = "> a\n>> b\n>\n> - c\n>   ```\n>   d\n>\n>   ```\n>";
+        let expected_markdown = "> a\n>\n> > b\n>\n> - c\n>   ```\n>   d\n>\n>   ```\n";
+        assert_markdown(input, expected_markdown);
+    }
+
+    #[test]
+    fn code_blocks() {
+        let input = "``` python\n    print(\"a\")\n`````\n```\n```";
+        let expected_markdown = "```python\n    print(\"a\")\n```\n\n```\n```\n";
+        assert_markdown(input, expected_markdown);
+        assert_markdown("```a\\\\+b\n```", "```a\\\\+b\n```\n");
+    }
+
+    #[test]
+    fn links_and_code_spans() {
+        let input = "[a](<b c> 'd') ![e](f (\"g\")) ``h`i`` `` `j` ``";
+        let expected_markdown = "[a](<b c> \"d\") ![e](f '\"g\"') ``h`i`` `` `j` ``\n";
+        assert_markdown(input, expected_markdown);
+        assert_markdown("` `` `\nb", "``` `` ```\nb\n");
+    }
+
+    #[test]
+    fn tables() {
+        let input = "Option|Description\n:--|:--:\n`a\\|b` | c |\n|d|\n\n|e|f|g|h|\n|-|:-|-:|:-:|";
+        let expected_markdown = "| Option | Description |\n| :-- | :-: |\n| `a\\|b` | c |\n| d |  |\n\n| e | f | g | h |\n| --- | :-- | --: | :-: |\n";
+        assert_markdown(input, expected_markdown);
+    }
+
+    #[test]
+    fn samples() {
+        for input in [include_str!("../test.md"), include_str!("../test2.md")] {
+            let document = parse(input);
+            let markdown = render(&document);
+            assert_eq!(render(&parse(&markdown)), markdown);
+            assert_eq!(html::render(&parse(&markdown)), html::render(&document));
+        }
+    }
+}
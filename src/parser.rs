@@ -0,0 +1,1608 @@
+use crate::span::{Position, Span};
+use crate::tokenizer::{trim_blank_end, Alignment, Token, Tokenizer};
+use std::borrow::Cow;
+
+/// A parsed document, its top-level blocks in order.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Document {
+    pub children: Vec<Node>,
+}
+
+/// A block or inline element, with where it was in the input.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Node {
+    pub kind: NodeKind,
+    pub span: Span,
+}
+
+/// What a node is, along with its children when it has any.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum NodeKind {
+    Heading {
+        level: u8,
+        children: Vec<Node>,
+    },
+    Paragraph {
+        children: Vec<Node>,
+    },
+    /// A list is tight when no blank line separates its items, or the blocks
+    /// inside of them.
+    List {
+        start: Option<u32>,
+        tight: bool,
+        children: Vec<Node>,
+    },
+    ListItem {
+        children: Vec<Node>,
+    },
+    Blockquote {
+        children: Vec<Node>,
+    },
+    CodeBlock {
+        lang: String,
+        text: String,
+    },
+    HorizontalRule,
+    Emphasis {
+        children: Vec<Node>,
+    },
+    Strong {
+        children: Vec<Node>,
+    },
+    Strikethrough {
+        children: Vec<Node>,
+    },
+    Link {
+        destination: String,
+        title: Option<String>,
+        children: Vec<Node>,
+    },
+    Image {
+        source: String,
+        title: Option<String>,
+        children: Vec<Node>,
+    },
+    Table {
+        alignments: Vec<Alignment>,
+        children: Vec<Node>,
+    },
+    TableRow {
+        children: Vec<Node>,
+    },
+    TableCell {
+        children: Vec<Node>,
+    },
+    CodeSpan(String),
+    SoftBreak,
+    Text(String),
+}
+
+struct CodeBlock {
+    lang: String,
+    text: String,
+    indent: usize,
+    quotes: usize,
+    // The content column of the list item the fence is in.
+    column: usize,
+    span: Span,
+}
+
+enum Container {
+    Document,
+    Blockquote,
+    List {
+        marker: char,
+        start: Option<u32>,
+        tight: bool,
+    },
+    ListItem {
+        column: usize,
+    },
+}
+
+// The tokens of a single line, with their spans.
+type Line<'a> = Vec<(Token<'a>, Span)>;
+
+// The text of a paragraph line, with the position it starts at.
+type TextLine<'a> = (Cow<'a, str>, Position);
+
+/// Builds a document from one line at a time.
+pub struct Parser<'a> {
+    tokenizer: Tokenizer<'a>,
+    containers: Vec<(Container, Span, Vec<Node>)>,
+    paragraph: Option<Vec<TextLine<'a>>>,
+    // Tokenizes the paragraphs once their lines are all known.
+    inline_tokenizer: Tokenizer<'a>,
+    code_block: Option<CodeBlock>,
+    // The depth of the containers a blank line right above was in.
+    blank: Option<usize>,
+}
+
+impl Default for Parser<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Parser<'a> {
+    /// An empty document, ready for its first line.
+    pub fn new() -> Self {
+        Self {
+            tokenizer: Tokenizer::new(),
+            containers: vec![(Container::Document, Span::default(), vec![])],
+            paragraph: None,
+            inline_tokenizer: Tokenizer::new(),
+            code_block: None,
+            blank: None,
+        }
+    }
+
+    /// Like `Tokenizer::set_line`, `line` should keep its line ending.
+    pub fn push_line(&mut self, line: impl Into<Cow<'a, str>>) {
+        self.tokenizer.set_line(line);
+        let mut tokens: Line<'a> = self.tokenizer.line_tokens().collect();
+
+        if let Some(code_block) = self.code_block.as_mut() {
+            let quotes = tokens
+                .iter()
+                .take_while(|(token, _)| *token == Token::Blockquote)
+                .count();
+            // A line indented less than the list item the fence is in ends both.
+            let outdented = tokens.get(quotes).is_some_and(|(_, span)| {
+                let text = self.tokenizer.text_from(span.start);
+                let indent = text.len() - text.trim_start_matches(' ').len();
+                !trim_blank_end(&text).is_empty() && indent < code_block.column
+            });
+            if quotes == code_block.quotes && !outdented {
+                if let Some((_, span)) = tokens.last() {
+                    code_block.span.end = span.end;
+                }
+                match tokens.get(quotes) {
+                    Some((Token::CodeBlock(_), _)) => self.close_code_block(),
+                    Some((Token::Literal(text), _)) => {
+                        let indent = text.len() - text.trim_start_matches(' ').len();
+                        code_block
+                            .text
+                            .push_str(&text[indent.min(code_block.indent)..]);
+                        code_block.text.push('\n');
+                    }
+                    _ => code_block.text.push('\n'),
+                }
+                return;
+            }
+            self.close_code_block();
+            if outdented {
+                self.tokenizer.end_fence();
+                tokens = self.tokenizer.line_tokens().collect();
+            }
+        }
+
+        self.handle_block(tokens, 1, 0);
+    }
+
+    /// Closes whatever blocks are still open and returns the document.
+    pub fn finish(mut self) -> Document {
+        self.close_code_block();
+        self.close_containers(1);
+        let (_, _, children) = self.containers.pop().unwrap();
+        Document { children }
+    }
+
+    // The line starts in the containers below `depth`, at column `indent`.
+    fn handle_block(&mut self, tokens: Line<'a>, depth: usize, indent: usize) {
+        let mut tokens = tokens.into_iter().peekable();
+        let mut depth = depth;
+        let mut indent = indent;
+        let mut new_quotes = vec![];
+        while let Some((token, span)) =
+            tokens.next_if(|(token, _)| matches!(token, Token::Indent(_) | Token::Blockquote))
+        {
+            if let Token::Indent(width) = token {
+                indent = width;
+                continue;
+            }
+
+            let quote = self.match_list_items(depth, indent);
+            if new_quotes.is_empty()
+                && matches!(
+                    self.containers.get(quote),
+                    Some((Container::Blockquote, ..))
+                )
+            {
+                depth = quote + 1;
+            } else {
+                if new_quotes.is_empty() {
+                    depth = quote;
+                }
+                new_quotes.push(span);
+            }
+            indent = 0;
+        }
+
+        let Some((mut first, mut first_span)) = tokens.peek().cloned() else {
+            return;
+        };
+        if new_quotes.is_empty() {
+            depth = self.match_list_items(depth, indent);
+        }
+
+        let column = match self.containers.get(depth - 1) {
+            Some((Container::ListItem { column }, ..)) => *column,
+            _ => 0,
+        };
+        let indented = indent >= column + 4;
+        // Only a paragraph open in the same containers can be underlined, which
+        // the tokenizer can't tell. So any line of `=` or `-` is checked here,
+        // whether it came as an underline, a rule or text.
+        if !indented
+            && self.paragraph.is_some()
+            && new_quotes.is_empty()
+            && depth == self.containers.len()
+        {
+            if let Some((level, span)) = self.find_underline(first_span.start) {
+                self.close_heading(level, span);
+                return;
+            }
+        }
+
+        if let Token::Underline(_) | Token::HorizontalRule = first {
+            // Below a lazy paragraph line an underline is read as it would be
+            // without any paragraph: a single `-` is an empty list item, two are
+            // text and three or more a rule. Indented by 4 or more, underlines
+            // and rules are both text.
+            let length = first_span.end.column - first_span.start.column;
+            let token = match (&first, length) {
+                _ if indented => None,
+                (Token::Underline(2), 1) => Some(Token::UnorderedList('-')),
+                (Token::Underline(2), 3..) | (Token::HorizontalRule, _) => {
+                    Some(Token::HorizontalRule)
+                }
+                _ => None,
+            };
+            let line = match token {
+                Some(token) => vec![(token, first_span)],
+                None => {
+                    let text = Token::Literal(self.tokenizer.text(first_span));
+                    vec![(Token::Paragraph, first_span), (text, first_span)]
+                }
+            };
+            tokens = line.into_iter().peekable();
+            (first, first_span) = tokens.peek().cloned().unwrap();
+        }
+
+        // A paragraph line continues the open paragraph, even when it doesn't
+        // match all of the open containers.
+        if self.paragraph.is_some() && new_quotes.is_empty() {
+            let empty = tokens.len() == 1;
+            let continues = match first {
+                Token::Paragraph => true,
+                // Indented by 4 or more, a line can't start a block.
+                _ if indented => true,
+                // Only a list starting at 1 can interrupt a paragraph, and only
+                // with an item that isn't empty. Otherwise the marker is text.
+                Token::UnorderedList(_) | Token::OrderedList(..) => {
+                    depth == self.containers.len()
+                        && (empty || matches!(first, Token::OrderedList(start, _) if start != 1))
+                }
+                _ => false,
+            };
+            if continues {
+                if let Token::CodeBlock(_) = first {
+                    self.tokenizer.end_fence();
+                }
+                self.push_paragraph_line(tokens);
+                return;
+            }
+        }
+
+        if let Token::Table(_) = first {
+            // The paragraph line right above is the header of the table.
+            if let Some(lines) = self.paragraph.as_mut() {
+                lines.pop();
+            }
+        }
+        // The item a new quote starts in can be loosened by a blank line too.
+        let matched = depth;
+        if !new_quotes.is_empty() {
+            self.close_containers(depth);
+            for span in new_quotes {
+                self.containers.push((Container::Blockquote, span, vec![]));
+            }
+            depth = self.containers.len();
+        }
+        if first == Token::Blank {
+            // A blank line ends the paragraph but not the list items around it.
+            let quoted = depth;
+            while let Some((Container::List { .. } | Container::ListItem { .. }, ..)) =
+                self.containers.get(depth)
+            {
+                depth += 1;
+            }
+            self.close_containers(depth);
+            // An item can start with one blank line at most.
+            if let Some((Container::ListItem { .. }, _, children)) = self.containers.last() {
+                if children.is_empty() {
+                    self.close_containers(self.containers.len() - 1);
+                }
+            }
+            self.blank = Some(quoted);
+            return;
+        }
+        if let Some(quoted) = self.blank.take() {
+            self.loosen_list(matched, quoted, &first);
+        }
+
+        if let Token::UnorderedList(marker) | Token::OrderedList(_, marker) = first {
+            tokens.next();
+            let start = match first {
+                Token::OrderedList(start, _) => Some(start),
+                _ => None,
+            };
+            // The marker spans the spaces up to the content, unless there is
+            // none and the content column is right after a space.
+            let text = self.tokenizer.text(first_span);
+            let width = match tokens.peek() {
+                Some(_) => text.len(),
+                None => trim_blank_end(&text).len() + 1,
+            };
+            self.open_list_item(depth, marker, start, indent + width, first_span);
+            // The rest of the line is the first block of the item.
+            let depth = self.containers.len();
+            self.handle_block(tokens.collect(), depth, indent + width);
+            return;
+        }
+
+        self.close_containers(depth);
+        let siblings = &mut self.containers.last_mut().unwrap().2;
+        let mut span = first_span;
+        let kind = match first {
+            Token::HorizontalRule => NodeKind::HorizontalRule,
+            Token::Table(alignments) => {
+                tokens.next();
+                let header = parse_row(tokens, alignments.len());
+                span = header.span.to(first_span);
+                NodeKind::Table {
+                    alignments,
+                    children: vec![header],
+                }
+            }
+            Token::TableRow => {
+                if let Some(Node {
+                    kind:
+                        NodeKind::Table {
+                            alignments,
+                            children,
+                        },
+                    span,
+                }) = siblings.last_mut()
+                {
+                    let row = parse_row(tokens, alignments.len());
+                    span.end = row.span.end;
+                    children.push(row);
+                    return;
+                }
+                self.push_paragraph_line(tokens);
+                return;
+            }
+            Token::CodeBlock(lang) => {
+                let quotes = self
+                    .containers
+                    .iter()
+                    .filter(|(container, ..)| matches!(container, Container::Blockquote))
+                    .count();
+                let column = match self.containers.last() {
+                    Some((Container::ListItem { column }, ..)) => *column,
+                    _ => 0,
+                };
+                self.code_block = Some(CodeBlock {
+                    lang: lang.into_owned(),
+                    text: String::new(),
+                    indent,
+                    quotes,
+                    column,
+                    span,
+                });
+                return;
+            }
+            Token::Header(level) => {
+                tokens.next();
+                let line: Line<'a> = tokens.collect();
+                if let Some((_, last)) = line.last() {
+                    span = span.to(*last);
+                }
+                NodeKind::Heading {
+                    level,
+                    children: parse_inline(line),
+                }
+            }
+            _ => {
+                self.push_paragraph_line(tokens);
+                return;
+            }
+        };
+        self.push_node(Node { kind, span });
+    }
+
+    // Skips past the open list items, starting at `depth`, that the line is
+    // indented into.
+    fn match_list_items(&self, depth: usize, indent: usize) -> usize {
+        let mut depth = depth;
+        while let (Some((Container::List { .. }, ..)), Some((Container::ListItem { column }, ..))) =
+            (self.containers.get(depth), self.containers.get(depth + 1))
+        {
+            if *column > indent {
+                break;
+            }
+            depth += 2;
+        }
+        depth
+    }
+
+    // A list goes loose when a blank line comes between two of its items, or
+    // between two blocks of an item. A blank line `quoted` deep, in a quote
+    // inside of the list, doesn't count.
+    fn loosen_list(&mut self, depth: usize, quoted: usize, first: &Token) {
+        let list = match (first, self.containers.get(depth)) {
+            (
+                Token::UnorderedList(marker) | Token::OrderedList(_, marker),
+                Some((
+                    Container::List {
+                        marker: list_marker,
+                        ..
+                    },
+                    ..,
+                )),
+            ) if marker == list_marker => depth,
+            _ => match self.containers.get(depth - 1) {
+                Some((Container::ListItem { .. }, _, children))
+                    if !children.is_empty() || self.containers.len() > depth =>
+                {
+                    depth - 2
+                }
+                _ => return,
+            },
+        };
+        if let (Some((Container::List { tight, .. }, ..)), true) =
+            (self.containers.get_mut(list), list >= quoted)
+        {
+            *tight = false;
+        }
+    }
+
+    // A list marker either continues the list right after the matched
+    // containers or starts a new one when the marker character changes.
+    fn open_list_item(
+        &mut self,
+        depth: usize,
+        marker: char,
+        start: Option<u32>,
+        column: usize,
+        span: Span,
+    ) {
+        let same_list = matches!(
+            self.containers.get(depth),
+            Some((Container::List { marker: list_marker, .. }, ..)) if *list_marker == marker
+        );
+
+        if same_list {
+            self.close_containers(depth + 1);
+        } else {
+            self.close_containers(depth);
+            let list = Container::List {
+                marker,
+                start,
+                tight: true,
+            };
+            self.containers.push((list, span, vec![]));
+        }
+        self.containers
+            .push((Container::ListItem { column }, span, vec![]));
+    }
+
+    // A container spans from its marker to the end of its last child.
+    fn close_containers(&mut self, len: usize) {
+        self.close_paragraph();
+        while self.containers.len() > len {
+            let (container, span, children) = self.containers.pop().unwrap();
+            let span = children.last().map_or(span, |last| span.to(last.span));
+            let kind = match container {
+                Container::Blockquote => NodeKind::Blockquote { children },
+                Container::List { start, tight, .. } => NodeKind::List {
+                    start,
+                    tight,
+                    children,
+                },
+                _ => NodeKind::ListItem { children },
+            };
+            self.push_node(Node { kind, span });
+        }
+    }
+
+    fn push_node(&mut self, node: Node) {
+        self.close_paragraph();
+        self.containers.last_mut().unwrap().2.push(node);
+    }
+
+    // Paragraph lines are kept as text from their first token on, to be
+    // tokenized again as a whole once the paragraph closes.
+    fn push_paragraph_line(&mut self, tokens: impl IntoIterator<Item = (Token<'a>, Span)>) {
+        let Some((_, span)) = tokens.into_iter().next() else {
+            return;
+        };
+        // The spaces after a marker that starts the line aren't part of it.
+        let text = self.tokenizer.text_from(span.start);
+        let start = shift(
+            span.start,
+            (text.len() - text.trim_start_matches([' ', '\t']).len()) as isize,
+        );
+        let line = (self.tokenizer.text_from(start), start);
+        self.paragraph.get_or_insert_with(Vec::new).push(line);
+    }
+
+    // The inlines of the paragraph, and the span from its first line to the
+    // end of the text on its last one.
+    fn take_paragraph(&mut self) -> Option<(Vec<Node>, Span)> {
+        let lines = self.paragraph.take()?;
+        let (_, start) = lines.first()?;
+        let (text, end) = lines.last()?;
+        let span = Span {
+            start: *start,
+            end: shift(*end, trim_blank_end(text).len() as isize),
+        };
+        self.inline_tokenizer.set_text(lines);
+        let mut tokens: Vec<Line> = vec![];
+        let mut last = span.start;
+        for (token, span) in self.inline_tokenizer.line_tokens() {
+            // A token on a line below the last one's end comes after a break.
+            if tokens.is_empty() || span.start.line > last.line {
+                tokens.push(vec![]);
+            }
+            last = span.end;
+            tokens.last_mut().unwrap().push((token, span));
+        }
+        Some((parse_lines(tokens), span))
+    }
+
+    fn close_paragraph(&mut self) {
+        let Some((children, span)) = self.take_paragraph() else {
+            return;
+        };
+        self.containers.last_mut().unwrap().2.push(Node {
+            kind: NodeKind::Paragraph { children },
+            span,
+        });
+    }
+
+    // The rest of the line from `start` underlines a heading when it's a run of
+    // `=` or `-`, with only whitespace after it.
+    fn find_underline(&self, start: Position) -> Option<(u8, Span)> {
+        let text = self.tokenizer.text_from(start);
+        let underline = trim_blank_end(&text);
+        let level = match underline.chars().next()? {
+            '=' => 1,
+            '-' => 2,
+            _ => return None,
+        };
+        if !underline.bytes().all(|b| b == underline.as_bytes()[0]) {
+            return None;
+        }
+        let end = Position {
+            column: start.column + underline.len(),
+            offset: start.offset + underline.len(),
+            ..start
+        };
+        Some((level, Span { start, end }))
+    }
+
+    fn close_heading(&mut self, level: u8, underline: Span) {
+        let Some((children, span)) = self.take_paragraph() else {
+            return;
+        };
+        self.push_node(Node {
+            kind: NodeKind::Heading { level, children },
+            span: span.to(underline),
+        });
+    }
+
+    fn close_code_block(&mut self) {
+        let Some(CodeBlock {
+            lang, text, span, ..
+        }) = self.code_block.take()
+        else {
+            return;
+        };
+        self.push_node(Node {
+            kind: NodeKind::CodeBlock { lang, text },
+            span,
+        });
+    }
+}
+
+/// Parses a whole document at once.
+pub fn parse(input: &str) -> Document {
+    let mut parser = Parser::new();
+    for line in input.split_inclusive('\n') {
+        parser.push_line(line);
+    }
+    parser.finish()
+}
+
+// Rows are padded or truncated to the number of columns in the table.
+fn parse_row<'a>(tokens: impl IntoIterator<Item = (Token<'a>, Span)>, columns: usize) -> Node {
+    let mut row: Option<Span> = None;
+    let mut cells: Vec<(Option<Span>, Line<'a>)> = vec![(None, vec![])];
+    for (token, span) in tokens {
+        row = Some(row.map_or(span, |row| row.to(span)));
+        match token {
+            Token::TableRow => {}
+            Token::TableCell => cells.push((None, vec![])),
+            token => {
+                let (cell, tokens) = cells.last_mut().unwrap();
+                *cell = Some(cell.map_or(span, |cell| cell.to(span)));
+                tokens.push((token, span));
+            }
+        }
+    }
+    cells.resize(columns, (None, vec![]));
+
+    // Empty cells are placed at the end of the row.
+    let row = row.unwrap_or_default();
+    let empty = Span {
+        start: row.end,
+        end: row.end,
+    };
+    let children = cells
+        .into_iter()
+        .map(|(span, tokens)| Node {
+            kind: NodeKind::TableCell {
+                children: parse_inline(tokens),
+            },
+            span: span.unwrap_or(empty),
+        })
+        .collect();
+    Node {
+        kind: NodeKind::TableRow { children },
+        span: row,
+    }
+}
+
+fn parse_inline<'a>(tokens: impl IntoIterator<Item = (Token<'a>, Span)>) -> Vec<Node> {
+    parse_lines(vec![tokens.into_iter().collect()])
+}
+
+// A delimiter run that may still turn into emphasis. `length` counts the
+// markers that are left of the `run` it started as.
+struct Delimiter {
+    marker: char,
+    length: usize,
+    run: usize,
+    open: bool,
+    close: bool,
+    span: Span,
+}
+
+enum Inline {
+    Node(Node),
+    Delimiter(Delimiter),
+}
+
+// Nests the inlines across the lines of a block into the links they're in.
+// The delimiters within each link, and then the block, are paired up once all
+// of them are known.
+fn parse_lines(lines: Vec<Line>) -> Vec<Node> {
+    let mut stack: Vec<(Token, Span, Vec<Inline>)> =
+        vec![(Token::Paragraph, Span::default(), vec![])];
+    let mut last = Span::default();
+
+    for (i, line) in lines.into_iter().enumerate() {
+        if i > 0 {
+            let siblings = &mut stack.last_mut().unwrap().2;
+            trim_end(siblings);
+            // A break spans from the end of one line to the text of the next.
+            let end = line.first().map_or(last.end, |(_, span)| span.start);
+            siblings.push(Inline::Node(Node {
+                kind: NodeKind::SoftBreak,
+                span: Span {
+                    start: last.end,
+                    end,
+                },
+            }));
+        }
+        for (token, span) in line {
+            last = span;
+            match token {
+                Token::Delimiter {
+                    marker,
+                    length,
+                    open,
+                    close,
+                } => stack
+                    .last_mut()
+                    .unwrap()
+                    .2
+                    .push(Inline::Delimiter(Delimiter {
+                        marker,
+                        length,
+                        run: length,
+                        open,
+                        close,
+                        span,
+                    })),
+                Token::Link { .. } | Token::Image { .. } => stack.push((token, span, vec![])),
+                Token::LinkEnd | Token::ImageEnd => {
+                    if let Some(open) = stack.iter().rposition(|(marker, ..)| is_link(marker)) {
+                        close(&mut stack, open, span);
+                    }
+                }
+                Token::CodeSpan(code) => stack.last_mut().unwrap().2.push(Inline::Node(Node {
+                    kind: NodeKind::CodeSpan(code.into_owned()),
+                    span,
+                })),
+                Token::Literal(text) => push_text(&mut stack.last_mut().unwrap().2, &text, span),
+                _ => {}
+            }
+        }
+    }
+
+    while stack.len() > 1 {
+        unwind(&mut stack);
+    }
+    let mut inlines = stack.pop().unwrap().2;
+    trim_end(&mut inlines);
+    pair_delimiters(inlines)
+}
+
+fn trim_end(inlines: &mut Vec<Inline>) {
+    if let Some(Inline::Node(Node {
+        kind: NodeKind::Text(text),
+        span,
+    })) = inlines.last_mut()
+    {
+        let trimmed = text.len() - trim_blank_end(text).len();
+        text.truncate(text.len() - trimmed);
+        span.end.column -= trimmed;
+        span.end.offset -= trimmed;
+        if text.is_empty() {
+            inlines.pop();
+        }
+    }
+}
+
+fn is_link(marker: &Token) -> bool {
+    matches!(marker, Token::Link { .. } | Token::Image { .. })
+}
+
+fn close(stack: &mut Vec<(Token, Span, Vec<Inline>)>, open: usize, end: Span) {
+    while stack.len() > open + 1 {
+        unwind(stack);
+    }
+
+    let (marker, span, inlines) = stack.pop().unwrap();
+    let children = pair_delimiters(inlines);
+    let kind = match marker {
+        Token::Image { source, title } => NodeKind::Image {
+            source: source.into_owned(),
+            title: title.map(Cow::into_owned),
+            children,
+        },
+        Token::Link { destination, title } => NodeKind::Link {
+            destination: destination.into_owned(),
+            title: title.map(Cow::into_owned),
+            children,
+        },
+        _ => unreachable!("only links are closed"),
+    };
+    stack.last_mut().unwrap().2.push(Inline::Node(Node {
+        kind,
+        span: span.to(end),
+    }));
+}
+
+fn unwind(stack: &mut Vec<(Token, Span, Vec<Inline>)>) {
+    let (marker, span, inlines) = stack.pop().unwrap();
+    let parent = &mut stack.last_mut().unwrap().2;
+    let marker = match marker {
+        Token::Image { .. } => "![",
+        _ => "[",
+    };
+
+    push_text(parent, marker, span);
+    for inline in inlines {
+        match inline {
+            Inline::Node(Node {
+                kind: NodeKind::Text(text),
+                span,
+            }) => push_text(parent, &text, span),
+            inline => parent.push(inline),
+        }
+    }
+}
+
+// Pairs each closer, from left to right, with the nearest opener before it,
+// as in the CommonMark delimiter algorithm. Unpaired markers are left as text.
+// A closer that finds no opener marks how far down the next closer of its
+// kind has to look, so that no opener is looked at again and again.
+fn pair_delimiters(inlines: Vec<Inline>) -> Vec<Node> {
+    let mut output: Vec<Inline> = vec![];
+    // Where in `output` the delimiters that can still open are.
+    let mut openers: Vec<usize> = vec![];
+    // The openers below these are out of reach for closers of a marker, that
+    // can or can't open, with a run of a length modulo 3.
+    let mut bottoms = [[[0; 3]; 2]; 3];
+    for inline in inlines {
+        let Inline::Delimiter(mut close) = inline else {
+            output.push(inline);
+            continue;
+        };
+        while close.close && close.length > 0 {
+            let marker = match close.marker {
+                '*' => 0,
+                '_' => 1,
+                _ => 2,
+            };
+            let (open, run) = (usize::from(close.open), close.run % 3);
+            let bottom = bottoms[marker][open][run];
+            let found = openers[bottom..].iter().rposition(|&opener| {
+                matches!(&output[opener], Inline::Delimiter(open) if can_pair(open, &close))
+            });
+            let Some(found) = found else {
+                bottoms[marker][open][run] = openers.len();
+                break;
+            };
+            // The openers in between are left as text.
+            let opener = openers[bottom + found];
+            openers.truncate(bottom + found + 1);
+
+            let Inline::Delimiter(open) = &mut output[opener] else {
+                unreachable!("openers are delimiters");
+            };
+            // Strong emphasis takes two markers from each side, from the inside.
+            let used = if open.length >= 2 && close.length >= 2 {
+                2
+            } else {
+                1
+            };
+            open.length -= used;
+            open.span.end = shift(open.span.end, -(used as isize));
+            close.length -= used;
+            close.span.start = shift(close.span.start, used as isize);
+            let span = Span {
+                start: open.span.end,
+                end: close.span.start,
+            };
+
+            let mut children = vec![];
+            for inline in output.drain(opener + 1..) {
+                push_node(&mut children, inline);
+            }
+            let kind = match (close.marker, used) {
+                ('~', _) => NodeKind::Strikethrough { children },
+                (_, 2) => NodeKind::Strong { children },
+                _ => NodeKind::Emphasis { children },
+            };
+            if matches!(output.last(), Some(Inline::Delimiter(open)) if open.length == 0) {
+                output.pop();
+                openers.pop();
+            }
+            output.push(Inline::Node(Node { kind, span }));
+            for bottom in bottoms.iter_mut().flatten().flatten() {
+                *bottom = (*bottom).min(openers.len());
+            }
+        }
+
+        if close.length > 0 {
+            if close.open {
+                openers.push(output.len());
+            }
+            output.push(Inline::Delimiter(close));
+        }
+    }
+
+    let mut nodes = vec![];
+    for inline in output {
+        push_node(&mut nodes, inline);
+    }
+    nodes
+}
+
+// Under the rule of 3, a marker that can both open and close doesn't pair
+// up with one that makes for a multiple of 3, unless both runs are.
+fn can_pair(open: &Delimiter, close: &Delimiter) -> bool {
+    let multiple_of_3 = (open.run + close.run).is_multiple_of(3)
+        && !(open.run.is_multiple_of(3) && close.run.is_multiple_of(3));
+    open.open
+        && close.close
+        && open.marker == close.marker
+        && open.length > 0
+        && !((open.close || close.open) && multiple_of_3)
+}
+
+fn shift(position: Position, bytes: isize) -> Position {
+    Position {
+        column: position.column.saturating_add_signed(bytes),
+        offset: position.offset.saturating_add_signed(bytes),
+        ..position
+    }
+}
+
+// Delimiters that weren't paired up are turned back into their text.
+fn push_node(nodes: &mut Vec<Node>, inline: Inline) {
+    let (text, span) = match inline {
+        Inline::Node(Node {
+            kind: NodeKind::Text(text),
+            span,
+        }) => (text, span),
+        Inline::Node(node) => return nodes.push(node),
+        Inline::Delimiter(delimiter) => {
+            let text = delimiter.marker.to_string().repeat(delimiter.length);
+            (text, delimiter.span)
+        }
+    };
+    if !merge_text(nodes.last_mut(), &text, span) {
+        nodes.push(text_node(text, span));
+    }
+}
+
+fn push_text(inlines: &mut Vec<Inline>, text: &str, span: Span) {
+    let last = match inlines.last_mut() {
+        Some(Inline::Node(node)) => Some(node),
+        _ => None,
+    };
+    if !merge_text(last, text, span) {
+        inlines.push(Inline::Node(text_node(text.to_string(), span)));
+    }
+}
+
+// Appends to the text node that came right before, if there is one.
+fn merge_text(last: Option<&mut Node>, text: &str, span: Span) -> bool {
+    let Some(Node {
+        kind: NodeKind::Text(last),
+        span: last_span,
+    }) = last
+    else {
+        return false;
+    };
+    last.push_str(text);
+    last_span.end = span.end;
+    true
+}
+
+fn text_node(text: String, span: Span) -> Node {
+    Node {
+        kind: NodeKind::Text(text),
+        span,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const N: fn(NodeKind) -> Node = |kind| Node {
+        kind,
+        span: Span::default(),
+    };
+    const T: fn(&str) -> Node = |s| N(NodeKind::Text(s.to_string()));
+    const P: fn(Vec<Node>) -> Node = |children| N(NodeKind::Paragraph { children });
+    const LI: fn(&str) -> Node = |s| {
+        N(NodeKind::ListItem {
+            children: vec![P(vec![T(s)])],
+        })
+    };
+
+    // Compares the shape of the tree only, spans are checked separately.
+    fn clear_spans(nodes: &mut [Node]) {
+        for node in nodes {
+            node.span = Span::default();
+            match &mut node.kind {
+                NodeKind::Heading { children, .. }
+                | NodeKind::Paragraph { children }
+                | NodeKind::List { children, .. }
+                | NodeKind::ListItem { children }
+                | NodeKind::Blockquote { children }
+                | NodeKind::Emphasis { children }
+                | NodeKind::Strong { children }
+                | NodeKind::Strikethrough { children }
+                | NodeKind::Link { children, .. }
+                | NodeKind::Image { children, .. }
+                | NodeKind::Table { children, .. }
+                | NodeKind::TableRow { children }
+                | NodeKind::TableCell { children } => clear_spans(children),
+                _ => {}
+            }
+        }
+    }
+
+    fn assert_document(input: &str, expected_nodes: Vec<Node>) {
+        let mut nodes = parse(input).children;
+        clear_spans(&mut nodes);
+        assert_eq!(nodes, expected_nodes);
+    }
+
+    fn span(start: (usize, usize, usize), end: (usize, usize, usize)) -> Span {
+        let position = |(line, column, offset)| Position {
+            line,
+            column,
+            offset,
+        };
+        Span {
+            start: position(start),
+            end: position(end),
+        }
+    }
+
+    #[test]
+    fn heading() {
+        let expected_nodes = vec![N(NodeKind::Heading {
+            level: 2,
+            children: vec![T("Hello World")],
+        })];
+        assert_document("## Hello World", expected_nodes);
+    }
+
+    #[test]
+    fn setext_heading() {
+        let expected_nodes = vec![
+            N(NodeKind::Heading {
+                level: 1,
+                children: vec![T("a"), N(NodeKind::SoftBreak), T("b")],
+            }),
+            P(vec![T("c"), N(NodeKind::SoftBreak), T("---")]),
+        ];
+        assert_document("a\nb\n===\n\nc\n    ---", expected_nodes);
+    }
+
+    #[test]
+    fn horizontal_rules() {
+        let expected_nodes = vec![
+            P(vec![T("a")]),
+            N(NodeKind::HorizontalRule),
+            N(NodeKind::List {
+                start: None,
+                tight: true,
+                children: vec![
+                    LI("b"),
+                    N(NodeKind::ListItem {
+                        children: vec![N(NodeKind::HorizontalRule)],
+                    }),
+                ],
+            }),
+            P(vec![T("c"), N(NodeKind::SoftBreak), T("* * *")]),
+        ];
+        assert_document("a\n * * *\n- b\n- ___\n\nc\n    * * *", expected_nodes);
+        // Indented to the content of the item, a rule after a blank line is in it.
+        let expected_nodes = vec![N(NodeKind::List {
+            start: None,
+            tight: false,
+            children: vec![N(NodeKind::ListItem {
+                children: vec![P(vec![T("a")]), N(NodeKind::HorizontalRule)],
+            })],
+        })];
+        assert_document("+ a\n\n   - - -", expected_nodes);
+    }
+
+    #[test]
+    fn setext_heading_in_list() {
+        let expected_nodes = vec![
+            N(NodeKind::List {
+                start: None,
+                tight: true,
+                children: vec![
+                    N(NodeKind::ListItem {
+                        children: vec![N(NodeKind::Heading {
+                            level: 2,
+                            children: vec![T("a")],
+                        })],
+                    }),
+                    N(NodeKind::ListItem {
+                        children: vec![P(vec![T("b"), N(NodeKind::SoftBreak), T("===")])],
+                    }),
+                    LI("c"),
+                ],
+            }),
+            N(NodeKind::HorizontalRule),
+        ];
+        assert_document("- a\n  --\n- b\n===\n- c\n---", expected_nodes);
+    }
+
+    #[test]
+    fn setext_heading_after_lazy_line() {
+        let heading = |level, children| {
+            N(NodeKind::Blockquote {
+                children: vec![N(NodeKind::Heading { level, children })],
+            })
+        };
+        let expected_nodes = vec![heading(1, vec![T("a"), N(NodeKind::SoftBreak), T("b")])];
+        assert_document("> a\n  b\n> ===", expected_nodes);
+        let expected_nodes = vec![heading(2, vec![T("a"), N(NodeKind::SoftBreak), T("===")])];
+        assert_document("> a\n===\n>---", expected_nodes);
+        let expected_nodes = vec![N(NodeKind::Heading {
+            level: 1,
+            children: vec![T("--"), N(NodeKind::SoftBreak), T("---")],
+        })];
+        assert_document("--\n    ---\n===", expected_nodes);
+    }
+
+    #[test]
+    fn paragraph_per_line() {
+        let expected_nodes = vec![P(vec![T("Hello")]), P(vec![T("World")])];
+        assert_document("Hello\n\nWorld", expected_nodes);
+    }
+
+    #[test]
+    fn nested_modifiers() {
+        let expected_nodes = vec![P(vec![
+            T("a "),
+            N(NodeKind::Strikethrough {
+                children: vec![N(NodeKind::Strong {
+                    children: vec![N(NodeKind::Emphasis {
+                        children: vec![T("b")],
+                    })],
+                })],
+            }),
+            T(" c"),
+        ])];
+        assert_document("a ~~**_b_**~~ c", expected_nodes);
+    }
+
+    #[test]
+    fn unclosed_modifier() {
+        let expected_nodes = vec![P(vec![
+            T("a *b "),
+            N(NodeKind::Strong {
+                children: vec![T("c")],
+            }),
+        ])];
+        assert_document("a *b **c**", expected_nodes);
+    }
+
+    #[test]
+    fn intraword_delimiters() {
+        let expected_nodes = vec![P(vec![T("snake_case_name 2 * 3 * 4 a")])];
+        assert_document("snake_case_name 2 * 3 * 4 a", expected_nodes);
+        let expected_nodes = vec![P(vec![
+            T("foo"),
+            N(NodeKind::Emphasis {
+                children: vec![T("bar")],
+            }),
+            T("baz foo_bar_"),
+        ])];
+        assert_document("foo*bar*baz foo_bar_", expected_nodes);
+    }
+
+    #[test]
+    fn escapes() {
+        let expected_nodes = vec![P(vec![T("# *a* [b](c) \\d")])];
+        assert_document("\\# \\*a\\* \\[b](c) \\d", expected_nodes);
+    }
+
+    #[test]
+    fn delimiter_lengths() {
+        let expected_nodes = vec![
+            P(vec![
+                N(NodeKind::Emphasis {
+                    children: vec![N(NodeKind::Strong {
+                        children: vec![T("a")],
+                    })],
+                }),
+                T(" *"),
+                N(NodeKind::Emphasis {
+                    children: vec![T("b")],
+                }),
+            ]),
+            P(vec![N(NodeKind::Emphasis {
+                children: vec![T("c**d")],
+            })]),
+        ];
+        assert_document("***a*** **b*\n\n*c**d*", expected_nodes);
+    }
+
+    #[test]
+    fn list() {
+        let list = |tight, children| {
+            N(NodeKind::List {
+                start: None,
+                tight,
+                children,
+            })
+        };
+        assert_document(
+            "- a\n- b\n\n- c",
+            vec![list(false, vec![LI("a"), LI("b"), LI("c")])],
+        );
+        // Blank lines at the end of the list or inside of a quote in it don't
+        // make it loose.
+        let expected_nodes = vec![list(true, vec![LI("a")]), P(vec![T("b")])];
+        assert_document("- a\n\n\nb", expected_nodes);
+        let quote = N(NodeKind::Blockquote {
+            children: vec![P(vec![T("b")]), P(vec![T("c")])],
+        });
+        let item = N(NodeKind::ListItem {
+            children: vec![P(vec![T("a")]), quote],
+        });
+        assert_document(
+            "- a\n  > b\n  >\n  > c\n- d",
+            vec![list(true, vec![item, LI("d")])],
+        );
+        // But one before a quote that starts in an item does.
+        let quote = N(NodeKind::Blockquote {
+            children: vec![P(vec![T("b")])],
+        });
+        let item = N(NodeKind::ListItem {
+            children: vec![P(vec![T("a")]), quote],
+        });
+        assert_document("- a\n\n  > b", vec![list(false, vec![item])]);
+    }
+
+    #[test]
+    fn list_item_continuation() {
+        let expected_nodes = vec![N(NodeKind::List {
+            start: Some(1),
+            tight: false,
+            children: vec![
+                N(NodeKind::ListItem {
+                    children: vec![P(vec![T("a")]), P(vec![T("b")])],
+                }),
+                LI("c"),
+            ],
+        })];
+        assert_document("1. a\n\n   b\n2. c", expected_nodes);
+    }
+
+    #[test]
+    fn nested_list_after_blank_line() {
+        let list = |tight, children| {
+            N(NodeKind::List {
+                start: None,
+                tight,
+                children,
+            })
+        };
+        let item = N(NodeKind::ListItem {
+            children: vec![P(vec![T("a")]), list(true, vec![LI("b"), LI("c")])],
+        });
+        let expected_nodes = vec![list(false, vec![item]), P(vec![T("d")])];
+        assert_document("- a\n\n  - b\n  - c\n\nd", expected_nodes);
+        // An item can't start with more than one blank line.
+        let expected_nodes = vec![
+            list(true, vec![N(NodeKind::ListItem { children: vec![] })]),
+            P(vec![T("a")]),
+        ];
+        assert_document("-\n\n  a", expected_nodes);
+    }
+
+    #[test]
+    fn ordered_list() {
+        let expected_nodes = vec![
+            N(NodeKind::List {
+                start: Some(57),
+                tight: true,
+                children: vec![LI("foo"), LI("bar")],
+            }),
+            N(NodeKind::List {
+                start: None,
+                tight: true,
+                children: vec![LI("baz")],
+            }),
+        ];
+        assert_document("57. foo\n1. bar\n- baz", expected_nodes);
+    }
+
+    #[test]
+    fn code_block() {
+        let expected_nodes = vec![
+            N(NodeKind::CodeBlock {
+                lang: "rust".to_string(),
+                text: "fn main() {\n\n}\n".to_string(),
+            }),
+            N(NodeKind::HorizontalRule),
+        ];
+        assert_document("```rust\nfn main() {\n\n}\n```\n---", expected_nodes);
+    }
+
+    #[test]
+    fn link_inside_bold() {
+        let expected_nodes = vec![P(vec![
+            N(NodeKind::Strong {
+                children: vec![N(NodeKind::Link {
+                    destination: "https://github.com/nodeca/babelfish/".to_string(),
+                    title: None,
+                    children: vec![T("babelfish")],
+                })],
+            }),
+            T(" - developer friendly"),
+        ])];
+        assert_document(
+            "__[babelfish](https://github.com/nodeca/babelfish/)__ - developer friendly",
+            expected_nodes,
+        );
+    }
+
+    #[test]
+    fn modifier_across_link() {
+        let expected_nodes = vec![P(vec![
+            T("*a "),
+            N(NodeKind::Link {
+                destination: "u".to_string(),
+                title: None,
+                children: vec![T("b* c")],
+            }),
+        ])];
+        assert_document("*a [b* c](u)", expected_nodes);
+    }
+
+    #[test]
+    fn image() {
+        let expected_nodes = vec![P(vec![N(NodeKind::Image {
+            source: "minion.png".to_string(),
+            title: Some("The Minion".to_string()),
+            children: vec![T("Minion")],
+        })])];
+        assert_document("![Minion](minion.png 'The Minion')", expected_nodes);
+    }
+
+    #[test]
+    fn list_marker_change() {
+        let expected_nodes = vec![
+            N(NodeKind::List {
+                start: None,
+                tight: true,
+                children: vec![LI("a")],
+            }),
+            N(NodeKind::List {
+                start: None,
+                tight: true,
+                children: vec![LI("b")],
+            }),
+            N(NodeKind::List {
+                start: Some(1),
+                tight: true,
+                children: vec![LI("c")],
+            }),
+            N(NodeKind::List {
+                start: Some(2),
+                tight: true,
+                children: vec![LI("d")],
+            }),
+        ];
+        assert_document("- a\n* b\n1. c\n2) d", expected_nodes);
+    }
+
+    #[test]
+    fn nested_list() {
+        let item = |s: &str, children: Vec<Node>| {
+            N(NodeKind::ListItem {
+                children: [vec![P(vec![T(s)])], children].concat(),
+            })
+        };
+        let list = |children| {
+            N(NodeKind::List {
+                start: None,
+                tight: true,
+                children,
+            })
+        };
+        let expected_nodes = vec![
+            list(vec![LI("a")]),
+            list(vec![item(
+                "b",
+                vec![list(vec![item("c", vec![list(vec![LI("d")])])])],
+            )]),
+            N(NodeKind::Paragraph {
+                children: vec![T("e")],
+            }),
+        ];
+        assert_document("* a\n- b\n    + c\n        *       d\n\ne", expected_nodes);
+    }
+
+    #[test]
+    fn multi_line_paragraph() {
+        let expected_nodes = vec![
+            P(vec![
+                T("a "),
+                N(NodeKind::Strong {
+                    children: vec![T("b"), N(NodeKind::SoftBreak), T("c")],
+                }),
+                N(NodeKind::SoftBreak),
+                T("2. d"),
+            ]),
+            P(vec![T("e")]),
+        ];
+        assert_document("a **b  \nc**\n2. d\n\ne", expected_nodes);
+    }
+
+    #[test]
+    fn inlines_across_lines() {
+        let expected_nodes = vec![P(vec![
+            T("see "),
+            N(NodeKind::Link {
+                destination: "http://x".to_string(),
+                title: None,
+                children: vec![T("the"), N(NodeKind::SoftBreak), T("docs")],
+            }),
+        ])];
+        assert_document("see [the\ndocs](http://x)", expected_nodes);
+        let expected_nodes = vec![P(vec![
+            T("a "),
+            N(NodeKind::CodeSpan("b c".to_string())),
+            T(" d"),
+        ])];
+        assert_document("a `b\n  c` d", expected_nodes);
+    }
+
+    #[test]
+    fn empty_item_in_paragraph() {
+        let expected_nodes = vec![P(vec![
+            T("a"),
+            N(NodeKind::SoftBreak),
+            T("1."),
+            N(NodeKind::SoftBreak),
+            T("*"),
+        ])];
+        assert_document("a\n1.\n*", expected_nodes);
+    }
+
+    #[test]
+    fn lazy_continuation() {
+        let expected_nodes = vec![
+            N(NodeKind::List {
+                start: None,
+                tight: true,
+                children: vec![N(NodeKind::ListItem {
+                    children: vec![P(vec![T("a"), N(NodeKind::SoftBreak), T("b")])],
+                })],
+            }),
+            N(NodeKind::Blockquote {
+                children: vec![P(vec![T("c"), N(NodeKind::SoftBreak), T("d")])],
+            }),
+            N(NodeKind::List {
+                start: Some(1),
+                tight: true,
+                children: vec![LI("e"), LI("f")],
+            }),
+        ];
+        assert_document("- a\n  b\n> c\nd\n1. e\n2. f", expected_nodes);
+    }
+
+    #[test]
+    fn code_block_in_list() {
+        let expected_nodes = vec![N(NodeKind::List {
+            start: Some(1),
+            tight: true,
+            children: vec![N(NodeKind::ListItem {
+                children: vec![
+                    P(vec![T("a")]),
+                    N(NodeKind::CodeBlock {
+                        lang: "".to_string(),
+                        text: "b\n c\n".to_string(),
+                    }),
+                ],
+            })],
+        })];
+        assert_document("1. a\n   ```\n   b\n    c\n   ```", expected_nodes);
+    }
+
+    #[test]
+    fn blockquote() {
+        let quote = |children| N(NodeKind::Blockquote { children });
+        let expected_nodes = vec![
+            quote(vec![P(vec![T("a")]), quote(vec![P(vec![T("b")])])]),
+            quote(vec![N(NodeKind::List {
+                start: None,
+                tight: true,
+                children: vec![N(NodeKind::ListItem {
+                    children: vec![P(vec![T("c"), N(NodeKind::SoftBreak), T("d")])],
+                })],
+            })]),
+        ];
+        assert_document("> a\n> > b\n\n> - c\nd", expected_nodes);
+    }
+
+    #[test]
+    fn blockquote_in_list() {
+        let expected_nodes = vec![N(NodeKind::List {
+            start: None,
+            tight: true,
+            children: vec![N(NodeKind::ListItem {
+                children: vec![
+                    P(vec![T("a")]),
+                    N(NodeKind::Blockquote {
+                        children: vec![N(NodeKind::CodeBlock {
+                            lang: "sh".to_string(),
+                            text: "b\n".to_string(),
+                        })],
+                    }),
+                ],
+            })],
+        })];
+        assert_document("- a\n  > ```sh\n  > b\n  > ```", expected_nodes);
+    }
+
+    #[test]
+    fn long_delimiter_lines() {
+        let emphasis = N(NodeKind::Emphasis {
+            children: vec![T("a")],
+        });
+        let repeat = |nodes: [Node; 2], count| nodes.iter().cycle().take(count).cloned().collect();
+        let expected_children = repeat([emphasis.clone(), T(" ")], 39_999);
+        assert_document(&"*a* ".repeat(20_000), vec![P(expected_children)]);
+        let expected_children = repeat([T("a"), emphasis], 20_000);
+        assert_document(&"a*".repeat(20_000), vec![P(expected_children)]);
+        let line = "a* ".repeat(20_000);
+        assert_document(&line, vec![P(vec![T(line.trim_end())])]);
+    }
+
+    #[test]
+    fn code_span() {
+        let expected_nodes = vec![P(vec![
+            N(NodeKind::Emphasis {
+                children: vec![T("a "), N(NodeKind::CodeSpan("*b*".to_string()))],
+            }),
+            T(" c"),
+        ])];
+        assert_document("_a `*b*`_ c", expected_nodes);
+    }
+
+    #[test]
+    fn table() {
+        let row = |cells: Vec<&str>| {
+            N(NodeKind::TableRow {
+                children: cells
+                    .into_iter()
+                    .map(|cell| {
+                        N(NodeKind::TableCell {
+                            children: if cell.is_empty() {
+                                vec![]
+                            } else {
+                                vec![T(cell)]
+                            },
+                        })
+                    })
+                    .collect(),
+            })
+        };
+        let expected_nodes = vec![
+            N(NodeKind::Table {
+                alignments: vec![Alignment::None, Alignment::Center],
+                children: vec![row(vec!["a", "b"]), row(vec!["c", ""]), row(vec!["e", "f"])],
+            }),
+            P(vec![T("g")]),
+        ];
+        assert_document(
+            "| a | b |\n|---|:-:|\n| c |\n| e | f | x |\n\ng",
+            expected_nodes,
+        );
+    }
+
+    #[test]
+    fn spans() {
+        let document = parse("- **a**\n  b\n\n> ```\n> c\n> ```\n");
+        let [list, quote] = &document.children[..] else {
+            panic!("expected a list and a blockquote");
+        };
+        assert_eq!(list.span, span((1, 1, 0), (2, 4, 11)));
+        let NodeKind::List { children, .. } = &list.kind else {
+            panic!("expected a list");
+        };
+        let NodeKind::ListItem { children } = &children[0].kind else {
+            panic!("expected a list item");
+        };
+        let NodeKind::Paragraph { children } = &children[0].kind else {
+            panic!("expected a paragraph");
+        };
+        let spans: Vec<Span> = children.iter().map(|node| node.span).collect();
+        let expected_spans = vec![
+            span((1, 3, 2), (1, 8, 7)),
+            span((1, 8, 7), (2, 3, 10)),
+            span((2, 3, 10), (2, 4, 11)),
+        ];
+        assert_eq!(spans, expected_spans);
+        assert_eq!(quote.span, span((4, 1, 13), (6, 6, 28)));
+    }
+}
@@ -0,0 +1,32 @@
+use std::ops::Range;
+
+/// Lines and columns count from 1, columns and offsets in bytes.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+    pub offset: usize,
+}
+
+/// Where a token or node starts and ends, the end being exclusive.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    /// The byte range of the span in the input.
+    pub fn range(&self) -> Range<usize> {
+        self.start.offset..self.end.offset
+    }
+
+    pub(crate) fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end,
+        }
+    }
+}
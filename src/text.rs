@@ -0,0 +1,125 @@
+use crate::parser::{Document, Node, NodeKind};
+
+/// What the plain text output includes.
+pub struct Options {
+    /// Includes the content of code blocks, not just of code spans.
+    pub code_blocks: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self { code_blocks: true }
+    }
+}
+
+/// The text of the document without any markup: one line per paragraph,
+/// heading, list item or table row, with a blank line between blocks.
+pub fn render(document: &Document, options: &Options) -> String {
+    let mut text = render_blocks(&document.children, "\n\n", options);
+    if !text.is_empty() {
+        text.push('\n');
+    }
+    text
+}
+
+// Blocks without any text, like rules, are left out.
+fn render_blocks(nodes: &[Node], separator: &str, options: &Options) -> String {
+    nodes
+        .iter()
+        .map(|node| render_block(node, options))
+        .filter(|block| !block.is_empty())
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+fn render_block(node: &Node, options: &Options) -> String {
+    match &node.kind {
+        NodeKind::Heading { children, .. } | NodeKind::Paragraph { children } => {
+            let mut text = String::new();
+            render_inlines(children, &mut text);
+            text.trim().to_string()
+        }
+        NodeKind::List { children, .. } => render_blocks(children, "\n", options),
+        NodeKind::ListItem { children } => render_blocks(children, "\n", options),
+        NodeKind::Blockquote { children } => render_blocks(children, "\n\n", options),
+        NodeKind::CodeBlock { text, .. } if options.code_blocks => {
+            text.trim_end_matches('\n').to_string()
+        }
+        NodeKind::Table { children, .. } => render_blocks(children, "\n", options),
+        NodeKind::TableRow { children } => {
+            let cells: Vec<String> = children
+                .iter()
+                .map(|cell| {
+                    let mut text = String::new();
+                    if let NodeKind::TableCell { children } = &cell.kind {
+                        render_inlines(children, &mut text);
+                    }
+                    text
+                })
+                .collect();
+            cells.join("\t").trim_end().to_string()
+        }
+        NodeKind::CodeBlock { .. } | NodeKind::HorizontalRule => String::new(),
+        _ => {
+            let mut text = String::new();
+            render_inlines(std::slice::from_ref(node), &mut text);
+            text
+        }
+    }
+}
+
+fn render_inlines(nodes: &[Node], text: &mut String) {
+    for node in nodes {
+        match &node.kind {
+            NodeKind::Emphasis { children }
+            | NodeKind::Strong { children }
+            | NodeKind::Strikethrough { children }
+            | NodeKind::Link { children, .. }
+            | NodeKind::Image { children, .. } => render_inlines(children, text),
+            NodeKind::Text(content) | NodeKind::CodeSpan(content) => text.push_str(content),
+            NodeKind::SoftBreak => text.push(' '),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::parse;
+
+    fn assert_text(input: &str, code_blocks: bool, expected_text: &str) {
+        let options = Options { code_blocks };
+        assert_eq!(render(&parse(input), &options), expected_text);
+    }
+
+    #[test]
+    fn markup() {
+        let input = "# a _b_\n\n**c** ~~d~~ [e](f) ![g](h) `i`\nj\n\n---";
+        assert_text(input, true, "a b\n\nc d e g i j\n");
+    }
+
+    #[test]
+    fn lists_and_blockquotes() {
+        let input = "- a\n  1. b\n  2. c\n- d\n\n> e\n>\n> - f";
+        assert_text(input, true, "a\nb\nc\nd\n\ne\n\nf\n");
+    }
+
+    #[test]
+    fn code_blocks() {
+        let input = "a\n\n```rust\nb\n\nc\n```\n\nd";
+        assert_text(input, true, "a\n\nb\n\nc\n\nd\n");
+        assert_text(input, false, "a\n\nd\n");
+    }
+
+    #[test]
+    fn tables() {
+        let input = "| a | b |\n|---|---|\n| c |\n| **d** | e |";
+        assert_text(input, true, "a\tb\nc\nd\te\n");
+    }
+
+    #[test]
+    fn empty() {
+        assert_text("---\n\n```\n```", false, "");
+    }
+}
@@ -1,17 +1,115 @@
+use crate::span::{Position, Span};
 use regex::Regex;
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead};
+use std::ops::Range;
+use std::str::SplitInclusive;
+use std::sync::OnceLock;
 
+// Traces the tokenizer when built with the `log` feature.
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "log")]
+        log::trace!($($arg)*);
+    };
+}
+
+/// A piece of a line, as it is read before any nesting is worked out.
 #[derive(Debug, PartialEq, Eq, Clone)]
-pub(crate) enum Token {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Token<'a> {
     Blank,
     HorizontalRule,
-    UnorderedList,
+    Blockquote,
+    Indent(usize),
+    UnorderedList(char),
+    OrderedList(u32, char),
     Paragraph,
-    Bold,
-    Italic,
-    Strikethrough,
-    CodeBlock(String),
+    /// A run of `*`, `_` or `~~`, with whether it can open and close emphasis.
+    Delimiter {
+        marker: char,
+        length: usize,
+        open: bool,
+        close: bool,
+    },
+    Link {
+        destination: Cow<'a, str>,
+        title: Option<Cow<'a, str>>,
+    },
+    LinkEnd,
+    Image {
+        source: Cow<'a, str>,
+        title: Option<Cow<'a, str>>,
+    },
+    ImageEnd,
+    CodeBlock(Cow<'a, str>),
+    CodeSpan(Cow<'a, str>),
+    Table(Vec<Alignment>),
+    TableRow,
+    TableCell,
     Header(u8),
-    Literal(String),
+    /// A line of `=` or `-` right below paragraph text, which makes the
+    /// paragraph a heading of level 1 or 2.
+    Underline(u8),
+    Literal(Cow<'a, str>),
+}
+
+impl Token<'_> {
+    /// Copies any borrowed text, so the token outlives its line.
+    pub fn into_owned(self) -> Token<'static> {
+        let owned = |text: Cow<str>| Cow::Owned(text.into_owned());
+        match self {
+            Token::Blank => Token::Blank,
+            Token::HorizontalRule => Token::HorizontalRule,
+            Token::Blockquote => Token::Blockquote,
+            Token::Indent(width) => Token::Indent(width),
+            Token::UnorderedList(marker) => Token::UnorderedList(marker),
+            Token::OrderedList(start, delimiter) => Token::OrderedList(start, delimiter),
+            Token::Paragraph => Token::Paragraph,
+            Token::Delimiter {
+                marker,
+                length,
+                open,
+                close,
+            } => Token::Delimiter {
+                marker,
+                length,
+                open,
+                close,
+            },
+            Token::Link { destination, title } => Token::Link {
+                destination: owned(destination),
+                title: title.map(owned),
+            },
+            Token::LinkEnd => Token::LinkEnd,
+            Token::Image { source, title } => Token::Image {
+                source: owned(source),
+                title: title.map(owned),
+            },
+            Token::ImageEnd => Token::ImageEnd,
+            Token::CodeBlock(lang) => Token::CodeBlock(owned(lang)),
+            Token::CodeSpan(code) => Token::CodeSpan(owned(code)),
+            Token::Table(alignments) => Token::Table(alignments),
+            Token::TableRow => Token::TableRow,
+            Token::TableCell => Token::TableCell,
+            Token::Header(level) => Token::Header(level),
+            Token::Underline(level) => Token::Underline(level),
+            Token::Literal(text) => Token::Literal(owned(text)),
+        }
+    }
+}
+
+/// How the cells of a table column are aligned.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Alignment {
+    None,
+    Left,
+    Center,
+    Right,
 }
 
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
@@ -23,104 +121,397 @@ enum State {
     End,
 }
 
-pub(crate) struct Tokenizer {
-    line: String,
+struct LinkTarget {
+    close: usize,
+    end: usize,
+    destination: Range<usize>,
+    title: Option<Range<usize>>,
+}
+
+/// Splits a single line at a time into tokens, carrying the block state that
+/// spans lines (fences, tables) over to the next `set_line`.
+pub struct Tokenizer<'a> {
+    line: Cow<'a, str>,
+    number: usize,
+    offset: usize,
+    next_offset: usize,
+    // Where each line of the text starts, as the joined lines of a paragraph
+    // have more than one.
+    starts: Vec<(usize, Position)>,
     cursor: usize,
+    span: Span,
     state: State,
+    // Where each bracket or parenthesis of the line that has a match closes.
+    brackets: HashMap<usize, usize>,
+    links: Vec<(usize, usize, bool)>,
+    quotes: usize,
+    fence_quotes: usize,
+    paragraph: Option<(usize, Position, Cow<'a, str>)>,
+    previous_paragraph: Option<(usize, Position, Cow<'a, str>)>,
+    // The quote depth of any paragraph text on the line, also in list items.
+    text: Option<usize>,
+    previous_text: Option<usize>,
+    table: Option<usize>,
+    row: bool,
+    pending: VecDeque<(Token<'a>, Span)>,
     header_pattern: Regex,
     ulist_pattern: Regex,
+    olist_pattern: Regex,
+    delimiter_row_pattern: Regex,
 }
 
-impl Tokenizer {
-    pub(crate) fn new() -> Self {
+impl<'a> Tokenizer<'a> {
+    /// A tokenizer with no line set yet.
+    pub fn new() -> Self {
+        // Compiling the patterns takes longer than parsing most documents, so
+        // it's only done once.
+        static PATTERNS: OnceLock<[Regex; 4]> = OnceLock::new();
+        let [header_pattern, ulist_pattern, olist_pattern, delimiter_row_pattern] = PATTERNS
+            .get_or_init(|| {
+                [
+                    r"^(#{1,6})([ \t]+|\r?\n|$)",
+                    r"^([-*+])([ \t]*\r?\n|[ \t]+|$)",
+                    r"^(\d{1,9})([.)])([ \t]*\r?\n|[ \t]+|$)",
+                    r"^\|?(\s*:?-+:?\s*\|)*\s*:?-+:?\s*\|?\s*$",
+                ]
+                .map(|pattern| Regex::new(pattern).unwrap())
+            })
+            .clone();
         Self {
-            line: String::default(),
+            line: Cow::Borrowed(""),
+            number: 0,
+            offset: 0,
+            next_offset: 0,
+            starts: vec![],
             cursor: 0,
-            state: State::Start,
-            header_pattern: Regex::new(r"^(#{1,6})[^#]\s*(.+)$").unwrap(),
-            ulist_pattern: Regex::new(r"^\s*([-*+])\s+").unwrap(),
+            span: Span::default(),
+            state: State::End,
+            brackets: HashMap::new(),
+            links: vec![],
+            quotes: 0,
+            fence_quotes: 0,
+            paragraph: None,
+            previous_paragraph: None,
+            text: None,
+            previous_text: None,
+            table: None,
+            row: false,
+            pending: VecDeque::new(),
+            header_pattern,
+            ulist_pattern,
+            olist_pattern,
+            delimiter_row_pattern,
         }
     }
 
-    pub(crate) fn set_line(&mut self, line: &String) {
-        println!("line: {:?}", line);
-        self.line = line.to_owned();
+    /// Byte offsets count `line` as given, so it should keep its line ending.
+    /// Tokens borrow from a borrowed line and copy out of an owned one.
+    pub fn set_line(&mut self, line: impl Into<Cow<'a, str>>) {
+        let mut line = line.into();
+        self.number += 1;
+        self.offset = self.next_offset;
+        self.next_offset += line.len();
+        let ending = line.len() - line.trim_end_matches(['\n', '\r']).len();
+        match &mut line {
+            Cow::Borrowed(text) => *text = &text[..text.len() - ending],
+            Cow::Owned(text) => text.truncate(text.len() - ending),
+        }
+        self.line = line;
+        trace!("line {}: {:?}", self.number, self.line);
+        self.starts.clear();
+        let start = Position {
+            line: self.number,
+            column: 1,
+            offset: self.offset,
+        };
+        self.starts.push((0, start));
         self.cursor = 0;
+        self.brackets = self.match_brackets();
+        self.drop_nested_links();
+        self.links.clear();
+        self.quotes = 0;
+        self.previous_paragraph = self.paragraph.take();
+        self.previous_text = self.text.take();
+        if !self.row {
+            self.table = None;
+        }
+        self.row = false;
         if self.state != State::CodeBlock {
-            self.state = State::Start;
+            self.set_state(State::Start);
+        }
+    }
+
+    // Reads the current line again from its start, as a line outside of the
+    // fence it was read in, or that it opened.
+    pub(crate) fn end_fence(&mut self) {
+        self.cursor = 0;
+        self.quotes = 0;
+        self.links.clear();
+        self.pending.clear();
+        self.set_state(State::Start);
+    }
+
+    // Tokenizes just the inlines of a paragraph, with its lines joined by `\n`
+    // so that links and code spans can go across them. Each line comes with
+    // the position it starts at.
+    pub(crate) fn set_text(&mut self, lines: Vec<(Cow<'a, str>, Position)>) {
+        self.starts.clear();
+        let mut text = String::new();
+        for (i, (line, start)) in lines.iter().enumerate() {
+            if i > 0 {
+                text.push('\n');
+            }
+            self.starts.push((text.len(), *start));
+            text.push_str(line);
+        }
+        self.line = match <[_; 1]>::try_from(lines) {
+            Ok([(line, _)]) => line,
+            Err(_) => Cow::Owned(text),
+        };
+        self.cursor = 0;
+        self.brackets = self.match_brackets();
+        self.drop_nested_links();
+        self.links.clear();
+        self.pending.clear();
+        self.row = false;
+        self.set_state(State::Process);
+    }
+
+    /// The span of the token returned last.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    fn set_state(&mut self, state: State) {
+        if state != self.state {
+            trace!(
+                "line {}, byte {}: {:?} -> {:?}",
+                self.number,
+                self.cursor,
+                self.state,
+                state
+            );
+        }
+        self.state = state;
+    }
+
+    fn position_at(&self, cursor: usize) -> Position {
+        let line = self.starts.partition_point(|(start, _)| *start <= cursor);
+        let (start, position) = self.starts[line.saturating_sub(1)];
+        Position {
+            column: position.column + cursor - start,
+            offset: position.offset + cursor - start,
+            ..position
         }
     }
+}
 
-    pub(crate) fn next(&mut self) -> Option<Token> {
+impl Default for Tokenizer<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Tokenizer<'a> {
+    /// Yields the tokens of the current line, with their spans. The iterator
+    /// ends with the line, and only picks up again after the next `set_line`.
+    pub fn line_tokens(&mut self) -> impl Iterator<Item = (Token<'a>, Span)> + '_ {
+        std::iter::from_fn(move || {
+            let token = self.next()?;
+            Some((token, self.span))
+        })
+    }
+
+    // The next token of the current line, if there's any left.
+    pub(crate) fn next(&mut self) -> Option<Token<'a>> {
+        if let Some((token, span)) = self.pending.pop_front() {
+            self.span = span;
+            return Some(token);
+        }
+
+        // Only the joined lines of a paragraph have line breaks, which aren't
+        // part of any token.
+        if self.state == State::Process && self.rest().starts_with('\n') {
+            self.cursor += 1;
+        }
+        let start = self.cursor;
+        let token = self.next_token()?;
+        let mut end = self.cursor;
+        // Whitespace trimmed off of a literal isn't part of its span.
+        if let Token::Literal(text) = &token {
+            if !text.ends_with(char::is_whitespace) {
+                end = start + trim_blank_end(&self.line[start..end]).len();
+            }
+        }
+        self.span = Span {
+            start: self.position_at(start),
+            end: self.position_at(end),
+        };
+        Some(token)
+    }
+}
+
+impl<'a> Tokenizer<'a> {
+    fn next_token(&mut self) -> Option<Token<'a>> {
         let mut literal_start = 0;
         loop {
-            let Some(current) = self.line.chars().nth(self.cursor) else {
+            let Some(current) = self.rest().chars().next() else {
+                if self.state == State::CodeBlock && self.quotes < self.fence_quotes {
+                    self.set_state(State::Start);
+                }
                 let token = match self.state {
                     State::Text => {
-                        let literal = self.line[literal_start..self.cursor].to_string();
-                        Some(Token::Literal(literal))
+                        let literal = &self.line[literal_start..self.cursor];
+                        let end = if self.row {
+                            literal_start + trim_blank_end(literal).len()
+                        } else {
+                            self.cursor
+                        };
+                        Some(Token::Literal(self.slice(literal_start..end)))
                     }
                     State::Start => Some(Token::Blank),
                     _ => None,
                 };
 
                 if self.state != State::CodeBlock {
-                    self.state = State::End;
+                    self.set_state(State::End);
                 }
                 return token;
             };
             match (current, self.state) {
                 ('#', State::Start) => {
-                    self.state = State::Process;
+                    self.set_state(State::Process);
                     return Some(self.handle_header());
                 }
-                (' ' | '\t' | '-' | '_' | '*' | '+', State::Start) => {
-                    if let Some(token) = self.handle_horizontal_rule() {
-                        self.state = State::End;
+                (' ' | '\t', State::Start) => {
+                    if let Some(token) = self.handle_indent() {
                         return Some(token);
                     }
-
-                    self.state = State::Process;
-                    if let Some(token) = self.handle_ulist() {
+                }
+                ('|' | ':' | '-', State::Start) if self.is_delimiter_row() => {
+                    return Some(self.handle_table());
+                }
+                ('=' | '-', State::Start) if self.is_underline() => {
+                    return Some(self.handle_underline());
+                }
+                ('>', State::Start) => {
+                    return Some(self.handle_blockquote());
+                }
+                ('-' | '_' | '*' | '+', State::Start) => {
+                    if let Some(token) = self.handle_horizontal_rule() {
+                        self.set_state(State::End);
                         return Some(token);
                     }
+
+                    self.set_state(State::Process);
+                    return Some(
+                        self.handle_ulist()
+                            .unwrap_or_else(|| self.handle_paragraph()),
+                    );
                 }
-                ('`', State::Start) => {
-                    if self.line.starts_with("```") {
-                        let language = self.line.get(3..).unwrap_or_default().trim_start();
-                        self.state = State::CodeBlock;
-                        self.cursor = self.line.len();
-                        return Some(Token::CodeBlock(language.to_string()));
-                    }
+                ('`', State::Start) if self.is_opening_fence() => {
+                    let language = self.rest()[3..].trim();
+                    let start = self.line.len() - self.rest()[3..].trim_start().len();
+                    let token = Token::CodeBlock(self.unescape(start..start + language.len()));
+                    self.set_state(State::CodeBlock);
+                    self.fence_quotes = self.quotes;
+                    self.cursor = self.line.len();
+                    return Some(token);
                 }
-                ('`', State::CodeBlock) => {
-                    if self.line.ends_with("```") {
-                        self.state = State::End;
-                        return Some(Token::CodeBlock("".to_string()));
+                (_, State::CodeBlock) if self.quotes < self.fence_quotes => {
+                    let rest = self.rest();
+                    let spaces = rest.len() - rest.trim_start_matches(' ').len();
+                    if spaces < 4 && rest[spaces..].starts_with('>') {
+                        self.cursor += spaces;
+                        return Some(self.handle_blockquote());
                     }
+                    // The quote the fence was opened in ended, and so did the fence.
+                    self.set_state(State::Start);
+                }
+                (_, State::CodeBlock) if self.is_closing_fence() => {
+                    self.set_state(State::End);
+                    self.cursor = self.line.len();
+                    return Some(Token::CodeBlock(Cow::Borrowed("")));
+                }
+                ('0'..='9', State::Start) => {
+                    self.set_state(State::Process);
+                    return Some(
+                        self.handle_olist()
+                            .unwrap_or_else(|| self.handle_paragraph()),
+                    );
                 }
                 (_, State::Start) => {
-                    self.state = State::Process;
-                    return Some(Token::Paragraph);
+                    self.set_state(State::Process);
+                    return Some(self.handle_paragraph());
                 }
-                ('_' | '*' | '~', State::Process) => {
-                    return self.handle_text_modifier();
+                ('_' | '*' | '~', State::Process) if self.find_delimiter().is_some() => {
+                    return self.handle_delimiter();
                 }
                 (_, State::CodeBlock) => {
+                    let literal = self.slice(self.cursor..self.line.len());
                     self.cursor = self.line.len();
-                    return Some(Token::Literal(self.line.clone()));
+                    return Some(Token::Literal(literal));
+                }
+                ('[' | '!' | ']', State::Process) if self.is_link_marker(current) => {
+                    return self.handle_link(current);
+                }
+                ('`', State::Process) if self.find_code_span().is_some() => {
+                    return self.handle_code_span();
+                }
+                ('|', State::Process) if self.row => {
+                    if let Some(token) = self.handle_cell() {
+                        return Some(token);
+                    }
+                }
+                ('\\', State::Process) if self.is_escape(self.cursor) => {
+                    self.cursor += 2;
+                    return Some(Token::Literal(self.slice(self.cursor - 1..self.cursor)));
                 }
                 (_, State::Process) => {
-                    self.state = State::Text;
+                    self.set_state(State::Text);
                     literal_start = self.cursor;
                 }
+                ('_' | '*' | '~', State::Text) if self.find_delimiter().is_some() => {
+                    let literal = self.slice(literal_start..self.cursor);
+                    self.set_state(State::Process);
+                    return Some(Token::Literal(literal));
+                }
                 ('_' | '*' | '~', State::Text) => {
-                    let literal = self.line[literal_start..self.cursor].to_string();
-                    self.state = State::Process;
+                    // A run that can't open or close emphasis is literal as a whole.
+                    self.cursor += self.delimiter_run();
+                }
+                ('[' | '!' | ']', State::Text) if self.is_link_marker(current) => {
+                    let literal = self.slice(literal_start..self.cursor);
+                    self.set_state(State::Process);
+                    return Some(Token::Literal(literal));
+                }
+                ('|', State::Text) if self.row => {
+                    let literal = self.line[literal_start..self.cursor].trim_end();
+                    let literal = self.slice(literal_start..literal_start + literal.len());
+                    self.set_state(State::Process);
+                    return Some(Token::Literal(literal));
+                }
+                ('\\', State::Text) if self.is_escape(self.cursor) => {
+                    let literal = self.slice(literal_start..self.cursor);
+                    self.set_state(State::Process);
+                    return Some(Token::Literal(literal));
+                }
+                ('`', State::Text) if self.find_code_span().is_some() => {
+                    let literal = self.slice(literal_start..self.cursor);
+                    self.set_state(State::Process);
+                    return Some(Token::Literal(literal));
+                }
+                ('`', State::Text) => {
+                    // An unmatched backtick run is literal as a whole.
+                    self.cursor += self.backtick_run(self.cursor);
+                }
+                ('\n', State::Text) => {
+                    let literal = self.slice(literal_start..self.cursor);
+                    self.set_state(State::Process);
                     return Some(Token::Literal(literal));
                 }
                 (_, State::Text) => {
-                    self.cursor += 1;
+                    self.cursor += current.len_utf8();
                 }
                 (_, _) => {
                     return None;
@@ -129,94 +520,745 @@ impl Tokenizer {
         }
     }
 
-    fn handle_header(&mut self) -> Token {
-        let Some(caps) = self.header_pattern.captures(&self.line) else {
-            return Token::Paragraph;
+    // The text of the current line within `span`.
+    pub(crate) fn text(&self, span: Span) -> Cow<'a, str> {
+        self.slice(span.start.column - 1..span.end.column - 1)
+    }
+
+    // The text of the current line from `start` on.
+    pub(crate) fn text_from(&self, start: Position) -> Cow<'a, str> {
+        self.slice(start.column - 1..self.line.len())
+    }
+
+    // Slices the line, which only copies when the line itself is owned.
+    fn slice(&self, range: Range<usize>) -> Cow<'a, str> {
+        match &self.line {
+            Cow::Borrowed(line) => Cow::Borrowed(&line[range]),
+            Cow::Owned(line) => Cow::Owned(line[range].to_string()),
+        }
+    }
+
+    fn rest(&self) -> &str {
+        &self.line[self.cursor..]
+    }
+
+    fn handle_indent(&mut self) -> Option<Token<'a>> {
+        let rest = self.rest();
+        let content = rest.trim_start_matches([' ', '\t']);
+        let whitespace = rest.len() - content.len();
+        let blank = content.is_empty();
+        let indent = rest[..whitespace].chars().fold(0, |indent, c| match c {
+            '\t' => indent + 4 - indent % 4,
+            _ => indent + 1,
+        });
+
+        self.cursor += whitespace;
+        if blank {
+            return None;
+        }
+        Some(Token::Indent(indent))
+    }
+
+    // Inside of a table every line that would start a paragraph is a row.
+    fn handle_paragraph(&mut self) -> Token<'a> {
+        if self.table == Some(self.quotes) {
+            self.row = true;
+            let rest = self.rest();
+            let cells = rest.strip_prefix('|').unwrap_or(rest).trim_start();
+            self.cursor += rest.len() - cells.len();
+            return Token::TableRow;
+        }
+
+        self.text = Some(self.quotes);
+        self.paragraph = Some((
+            self.quotes,
+            self.position_at(self.cursor),
+            self.slice(self.cursor..self.line.len()),
+        ));
+        Token::Paragraph
+    }
+
+    // A delimiter row turns the paragraph line right above it into the header
+    // of a table with as many columns.
+    fn is_delimiter_row(&self) -> bool {
+        let Some((quotes, _, header)) = &self.previous_paragraph else {
+            return false;
+        };
+        let rest = self.rest();
+        *quotes == self.quotes
+            && rest.contains('|')
+            && self.delimiter_row_pattern.is_match(rest)
+            && count_cells(header) == count_cells(rest)
+    }
+
+    fn handle_table(&mut self) -> Token<'a> {
+        let alignments = self
+            .rest()
+            .trim()
+            .trim_matches('|')
+            .split('|')
+            .map(|cell| {
+                let cell = cell.trim();
+                match (cell.starts_with(':'), cell.ends_with(':')) {
+                    (true, true) => Alignment::Center,
+                    (true, false) => Alignment::Left,
+                    (false, true) => Alignment::Right,
+                    (false, false) => Alignment::None,
+                }
+            })
+            .collect();
+
+        if let Some((_, position, header)) = self.previous_paragraph.take() {
+            let mut row = Tokenizer::new();
+            row.set_line(header);
+            row.table = Some(0);
+            // Spans of the header row are relative to where it starts.
+            let shift = |Position { column, offset, .. }| Position {
+                line: position.line,
+                column: position.column + column - 1,
+                offset: position.offset + offset,
+            };
+            while let Some(token) = row.next() {
+                let Span { start, end } = row.span();
+                let span = Span {
+                    start: shift(start),
+                    end: shift(end),
+                };
+                self.pending.push_back((token, span));
+            }
+        }
+
+        self.table = Some(self.quotes);
+        self.row = true;
+        self.set_state(State::End);
+        self.cursor = self.line.len();
+        Token::Table(alignments)
+    }
+
+    fn handle_cell(&mut self) -> Option<Token<'a>> {
+        self.cursor += 1;
+        let rest = self.rest();
+        let whitespace = rest.len() - rest.trim_start().len();
+        let last = whitespace == rest.len();
+        self.cursor += whitespace;
+        if last {
+            return None;
+        }
+        Some(Token::TableCell)
+    }
+
+    // A backslash escapes any ASCII punctuation, which is then just text.
+    fn is_escape(&self, cursor: usize) -> bool {
+        let bytes = self.line.as_bytes();
+        bytes.get(cursor) == Some(&b'\\')
+            && bytes.get(cursor + 1).is_some_and(u8::is_ascii_punctuation)
+    }
+
+    // Slices the line with its escapes removed, copying only when there are any.
+    fn unescape(&self, range: Range<usize>) -> Cow<'a, str> {
+        if !range.clone().any(|cursor| self.is_escape(cursor)) {
+            return self.slice(range);
+        }
+        let mut text = String::new();
+        let mut cursor = range.start;
+        while cursor < range.end {
+            if self.is_escape(cursor) {
+                cursor += 1;
+            }
+            let c = self.line[cursor..].chars().next().unwrap_or_default();
+            text.push(c);
+            cursor += c.len_utf8();
+        }
+        Cow::Owned(text)
+    }
+
+    fn handle_header(&mut self) -> Token<'a> {
+        let Some(caps) = self.header_pattern.captures(self.rest()) else {
+            return self.handle_paragraph();
         };
 
         let level = caps[1].len() as u8;
-        self.cursor += caps[1].len() + 1;
+        // The content starts after the spaces, and an empty heading keeps
+        // its line ending.
+        self.cursor += caps[1].len() + caps[2].trim_end_matches(['\r', '\n']).len();
 
         Token::Header(level)
     }
 
-    fn handle_text_modifier(&mut self) -> Option<Token> {
-        let current = self.line.chars().nth(self.cursor)?;
-        let next = self.line.chars().nth(self.cursor + 1).unwrap_or_default();
+    fn handle_delimiter(&mut self) -> Option<Token<'a>> {
+        let token = self.find_delimiter()?;
+        self.cursor += self.delimiter_run();
+        Some(token)
+    }
+
+    // Whether the run at the cursor can open or close emphasis depends on the
+    // characters around it, the edges of the line counting as whitespace. A
+    // run that can do neither is just text, as is any `~` run but `~~`.
+    fn find_delimiter(&self) -> Option<Token<'a>> {
+        let marker = self.rest().chars().next()?;
+        let length = self.delimiter_run();
+        if marker == '~' && length != 2 {
+            return None;
+        }
+
+        let before = self.line[..self.cursor].chars().next_back().unwrap_or(' ');
+        let after = self.rest()[length..].chars().next().unwrap_or(' ');
+        let left_flanking = !after.is_whitespace()
+            && (!is_punctuation(after) || before.is_whitespace() || is_punctuation(before));
+        let right_flanking = !before.is_whitespace()
+            && (!is_punctuation(before) || after.is_whitespace() || is_punctuation(after));
+        // `_` doesn't make for emphasis inside of words.
+        let (open, close) = match marker {
+            '_' => (
+                left_flanking && (!right_flanking || is_punctuation(before)),
+                right_flanking && (!left_flanking || is_punctuation(after)),
+            ),
+            _ => (left_flanking, right_flanking),
+        };
+        (open || close).then_some(Token::Delimiter {
+            marker,
+            length,
+            open,
+            close,
+        })
+    }
+
+    fn delimiter_run(&self) -> usize {
+        let rest = self.rest();
+        let marker = rest.chars().next().unwrap_or_default();
+        rest.len() - rest.trim_start_matches(marker).len()
+    }
+
+    // Pairs up the brackets and parentheses of the line in a single pass, so
+    // finding a link doesn't rescan the line for every `[`. Parentheses don't
+    // pair across whitespace, which a destination can't contain.
+    fn match_brackets(&self) -> HashMap<usize, usize> {
+        let mut brackets = HashMap::new();
+        let (mut squares, mut parens) = (vec![], vec![]);
+        let mut cursor = 0;
+        while let Some(b) = self.line.as_bytes().get(cursor) {
+            match b {
+                b'\\' if self.is_escape(cursor) => cursor += 1,
+                b'[' => squares.push(cursor),
+                b'(' => parens.push(cursor),
+                b']' | b')' => {
+                    let open = if *b == b']' {
+                        &mut squares
+                    } else {
+                        &mut parens
+                    };
+                    if let Some(open) = open.pop() {
+                        brackets.insert(open, cursor);
+                    }
+                }
+                b if b.is_ascii_whitespace() => parens.clear(),
+                _ => {}
+            }
+            cursor += 1;
+        }
+        brackets
+    }
 
-        self.cursor += 2;
-        match (current, next) {
-            ('~', '~') => Some(Token::Strikethrough),
-            ('*', '*') => Some(Token::Bold),
-            ('_', '_') => Some(Token::Bold),
-            _ => {
-                self.cursor -= 1;
-                Some(Token::Italic)
+    // A link can't contain another link, so the `[` of one that would is left
+    // unpaired, and read as text. As in the CommonMark algorithm links close in
+    // the order of their `]`, and a link that closes deactivates the `[` still
+    // open before it. An image can hold a link, though.
+    fn drop_nested_links(&mut self) {
+        let bytes = self.line.as_bytes();
+        let mut opens: Vec<(usize, bool)> = vec![];
+        // The opens below this index can't start a link anymore.
+        let mut active = 0;
+        let mut bang = None;
+        let mut cursor = 0;
+        while let Some(b) = bytes.get(cursor) {
+            match b {
+                b'\\' if self.is_escape(cursor) => cursor += 1,
+                b'!' => bang = Some(cursor),
+                b'[' => opens.push((cursor, bang.is_some_and(|bang| bang + 1 == cursor))),
+                b']' => {
+                    if let Some((open, image)) = opens.pop() {
+                        let index = opens.len();
+                        let inactive = !image && index < active;
+                        active = active.min(index);
+                        if inactive {
+                            self.brackets.remove(&open);
+                        } else if let Some(link) = self.find_link(open) {
+                            if !image {
+                                active = index;
+                            }
+                            cursor = link.end;
+                            continue;
+                        }
+                    }
+                }
+                _ => {}
             }
+            cursor += 1;
+        }
+    }
+
+    fn is_link_marker(&self, current: char) -> bool {
+        match current {
+            ']' => matches!(self.links.last(), Some((close, _, _)) if *close == self.cursor),
+            '[' => self.find_link(self.cursor).is_some(),
+            _ => self.rest()[1..].starts_with('[') && self.find_link(self.cursor + 1).is_some(),
         }
     }
 
-    fn handle_ulist(&mut self) -> Option<Token> {
-        let caps = self.ulist_pattern.captures(&self.line)?;
-        self.cursor += caps[0].len();
-        Some(Token::UnorderedList)
+    fn handle_link(&mut self, current: char) -> Option<Token<'a>> {
+        if current == ']' {
+            let (_, end, image) = self.links.pop()?;
+            self.cursor = end;
+            return Some(if image {
+                Token::ImageEnd
+            } else {
+                Token::LinkEnd
+            });
+        }
+
+        let image = current == '!';
+        let open = if image { self.cursor + 1 } else { self.cursor };
+        let link = self.find_link(open)?;
+        self.links.push((link.close, link.end, image));
+        self.cursor = open + 1;
+
+        let destination = self.unescape(link.destination);
+        let title = link.title.map(|title| self.unescape(title));
+        if image {
+            return Some(Token::Image {
+                source: destination,
+                title,
+            });
+        }
+        Some(Token::Link { destination, title })
     }
 
-    fn handle_horizontal_rule(&mut self) -> Option<Token> {
-        if self.line != "---" && self.line != "___" && self.line != "***" {
+    // Matches `[text](destination "title")` starting at the `[` in `open`.
+    fn find_link(&self, open: usize) -> Option<LinkTarget> {
+        let bytes = self.line.as_bytes();
+        if bytes.get(open) != Some(&b'[') {
+            return None;
+        }
+
+        let close = *self.brackets.get(&open)?;
+        let mut cursor = close + 1;
+        if bytes.get(cursor) != Some(&b'(') {
             return None;
         }
+        cursor += 1;
+        let skip_spaces = |cursor: &mut usize| {
+            while bytes.get(*cursor).is_some_and(|b| b.is_ascii_whitespace()) {
+                *cursor += 1;
+            }
+        };
+        skip_spaces(&mut cursor);
+
+        let destination;
+        if bytes.get(cursor) == Some(&b'<') {
+            cursor += 1;
+            let start = cursor;
+            loop {
+                match bytes.get(cursor)? {
+                    b'\\' if self.is_escape(cursor) => cursor += 1,
+                    b'>' => break,
+                    b'<' | b'\n' => return None,
+                    _ => {}
+                }
+                cursor += 1;
+            }
+            destination = start..cursor;
+            cursor += 1;
+        } else {
+            let start = cursor;
+            loop {
+                match bytes.get(cursor)? {
+                    b'\\' if self.is_escape(cursor) => cursor += 1,
+                    b')' => break,
+                    b if b.is_ascii_whitespace() => break,
+                    // Parentheses inside of a destination have to be balanced.
+                    b'(' => cursor = *self.brackets.get(&cursor)?,
+                    _ => {}
+                }
+                cursor += 1;
+            }
+            destination = start..cursor;
+        }
+
+        let before_title = cursor;
+        skip_spaces(&mut cursor);
+        let mut title = None;
+        let closing = match bytes.get(cursor) {
+            Some(b'"') => Some(b'"'),
+            Some(b'\'') => Some(b'\''),
+            Some(b'(') => Some(b')'),
+            _ => None,
+        };
+        if let Some(closing) = closing.filter(|_| cursor > before_title) {
+            cursor += 1;
+            let start = cursor;
+            while *bytes.get(cursor)? != closing {
+                // A title in parentheses can't hold an unescaped `(`.
+                if closing == b')' && bytes[cursor] == b'(' {
+                    return None;
+                }
+                if self.is_escape(cursor) {
+                    cursor += 1;
+                }
+                cursor += 1;
+            }
+            title = Some(start..cursor);
+            cursor += 1;
+            skip_spaces(&mut cursor);
+        }
+
+        if bytes.get(cursor) != Some(&b')') {
+            return None;
+        }
+
+        Some(LinkTarget {
+            close,
+            end: cursor + 1,
+            destination,
+            title,
+        })
+    }
+
+    fn handle_code_span(&mut self) -> Option<Token<'a>> {
+        let (end, code) = self.find_code_span()?;
+        self.cursor = end;
+        let code = self.slice(code);
+        // Line breaks inside of a code span are spaces.
+        if code.contains('\n') {
+            return Some(Token::CodeSpan(Cow::Owned(code.replace('\n', " "))));
+        }
+        if self.row && code.contains("\\|") {
+            return Some(Token::CodeSpan(Cow::Owned(code.replace("\\|", "|"))));
+        }
+        Some(Token::CodeSpan(code))
+    }
+
+    // Matches a backtick run at the cursor with the next run of the same
+    // length, returning where the span ends and its content.
+    fn find_code_span(&self) -> Option<(usize, Range<usize>)> {
+        let open = self.backtick_run(self.cursor);
+        let mut cursor = self.cursor + open;
+        loop {
+            cursor += self.line[cursor..].find('`')?;
+
+            let close = self.backtick_run(cursor);
+            if close == open {
+                let start = self.cursor + open;
+                let code = &self.line[start..cursor];
+                // One surrounding space is stripped, unless the span is all spaces.
+                let space = [' ', '\n'];
+                let padded = code.len() > 1 && code.starts_with(space) && code.ends_with(space);
+                if padded && code.contains(|c| !space.contains(&c)) {
+                    return Some((cursor + close, start + 1..cursor - 1));
+                }
+                return Some((cursor + close, start..cursor));
+            }
+            cursor += close;
+        }
+    }
+
+    fn backtick_run(&self, start: usize) -> usize {
+        self.line[start..]
+            .bytes()
+            .take_while(|b| *b == b'`')
+            .count()
+    }
+
+    fn handle_ulist(&mut self) -> Option<Token<'a>> {
+        let caps = self.ulist_pattern.captures(self.rest())?;
+        let marker = caps[1].chars().next()?;
+        self.cursor += item_marker_width(&caps[1], &caps[2]);
+        self.handle_item_content();
+        Some(Token::UnorderedList(marker))
+    }
+
+    fn handle_olist(&mut self) -> Option<Token<'a>> {
+        let caps = self.olist_pattern.captures(self.rest())?;
+        let start = caps[1].parse().ok()?;
+        let delimiter = caps[2].chars().next()?;
+        self.cursor += item_marker_width(&caps[0][..caps[1].len() + 1], &caps[3]);
+        self.handle_item_content();
+        Some(Token::OrderedList(start, delimiter))
+    }
+
+    // A list item can start with any block, like `- * * *`, `- # a` or
+    // `- > a`, which the start of the line reads again. Neither an underline
+    // nor a delimiter row can follow a marker though.
+    fn handle_item_content(&mut self) {
+        if self.starts_block() {
+            self.previous_text = None;
+            self.previous_paragraph = None;
+            self.set_state(State::Start);
+        } else if !trim_blank_end(self.rest()).is_empty() {
+            self.text = Some(self.quotes);
+        }
+    }
+
+    fn starts_block(&self) -> bool {
+        let rest = self.rest();
+        self.find_horizontal_rule().is_some()
+            || rest.starts_with('>')
+            || self.is_opening_fence()
+            || self.header_pattern.is_match(rest)
+            || self.ulist_pattern.is_match(rest)
+            || self.olist_pattern.is_match(rest)
+    }
+
+    fn handle_blockquote(&mut self) -> Token<'a> {
+        self.cursor += 1;
+        if self.rest().starts_with([' ', '\t']) {
+            self.cursor += 1;
+        }
+        self.quotes += 1;
+        Token::Blockquote
+    }
+
+    // A backtick fence can't have a backtick in its info string.
+    fn is_opening_fence(&self) -> bool {
+        let rest = self.rest();
+        rest.starts_with("```") && !rest.trim_start_matches('`').contains('`')
+    }
+
+    fn is_closing_fence(&self) -> bool {
+        let fence = self.rest().trim();
+        fence.starts_with("```") && fence.chars().all(|c| c == '`')
+    }
+
+    // Only the parser knows whether the text above is still an open paragraph
+    // in the same container, so a line of `-` is an underline here even when
+    // it could be a rule or a list item instead.
+    fn is_underline(&self) -> bool {
+        let underline = trim_blank_end(self.rest());
+        let marker = underline.chars().next().unwrap_or_default();
+        self.previous_text == Some(self.quotes) && underline.chars().all(|c| c == marker)
+    }
+
+    // Trailing whitespace isn't part of the span, which counts the markers.
+    fn handle_underline(&mut self) -> Token<'a> {
+        let level = if self.rest().starts_with('=') { 1 } else { 2 };
+        self.cursor += trim_blank_end(self.rest()).len();
+        self.set_state(State::End);
+        Token::Underline(level)
+    }
+
+    fn handle_horizontal_rule(&mut self) -> Option<Token<'a>> {
+        self.cursor += self.find_horizontal_rule()?;
         Some(Token::HorizontalRule)
     }
+
+    // Three or more of the same marker, with any spaces or tabs in between.
+    fn find_horizontal_rule(&self) -> Option<usize> {
+        let rule = trim_blank_end(self.rest());
+        let marker = rule
+            .chars()
+            .next()
+            .filter(|c| matches!(c, '-' | '_' | '*'))?;
+        let markers = rule.chars().filter(|c| *c == marker).count();
+        (markers >= 3 && rule.chars().all(|c| c == marker || c == ' ' || c == '\t'))
+            .then_some(rule.len())
+    }
+}
+
+/// Yields the tokens of a whole document, line by line.
+pub struct Tokens<'a, L> {
+    lines: L,
+    tokenizer: Tokenizer<'a>,
+    error: Option<io::Error>,
+}
+
+/// Tokenizes a whole document, borrowing from it.
+pub fn tokens(input: &str) -> Tokens<'_, SplitInclusive<'_, char>> {
+    Tokens::new(input.split_inclusive('\n'))
+}
+
+/// The lines of a reader, keeping their line endings.
+pub struct ReadLines<R>(R);
+
+impl<R: BufRead> Iterator for ReadLines<R> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<io::Result<String>> {
+        let mut line = String::new();
+        match self.0.read_line(&mut line) {
+            Ok(0) => None,
+            Ok(_) => Some(Ok(line)),
+            Err(error) => Some(Err(error)),
+        }
+    }
+}
+
+impl<'a, L> Tokens<'a, L> {
+    fn new(lines: L) -> Self {
+        Self {
+            lines,
+            tokenizer: Tokenizer::new(),
+            error: None,
+        }
+    }
+
+    /// The error that ended reading, if any.
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    /// The span of the token returned last.
+    pub fn span(&self) -> Span {
+        self.tokenizer.span()
+    }
+
+    /// Pairs each token with its span.
+    pub fn spanned(mut self) -> impl Iterator<Item = (Token<'a>, Span)>
+    where
+        Self: Iterator<Item = Token<'a>>,
+    {
+        std::iter::from_fn(move || {
+            let token = self.next()?;
+            Some((token, self.span()))
+        })
+    }
+
+    fn next_token<S: Into<Cow<'a, str>>>(
+        &mut self,
+        next_line: impl Fn(&mut Self) -> Option<S>,
+    ) -> Option<Token<'a>> {
+        loop {
+            if let Some(token) = self.tokenizer.next() {
+                return Some(token);
+            }
+            let line = next_line(self)?;
+            self.tokenizer.set_line(line);
+        }
+    }
+}
+
+// Lines read are owned, and so are their tokens.
+impl<R: BufRead> Tokens<'static, ReadLines<R>> {
+    /// Tokenizes the lines of a reader as they are read.
+    pub fn from_reader(reader: R) -> Self {
+        Self::new(ReadLines(reader))
+    }
+}
+
+impl<'a> Iterator for Tokens<'a, SplitInclusive<'a, char>> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        self.next_token(|tokens| tokens.lines.next())
+    }
+}
+
+// Reading stops at the first error, which is kept in `error`.
+impl<R: BufRead> Iterator for Tokens<'static, ReadLines<R>> {
+    type Item = Token<'static>;
+
+    fn next(&mut self) -> Option<Token<'static>> {
+        self.next_token(|tokens| match tokens.lines.next()? {
+            Ok(line) => Some(line),
+            Err(error) => {
+                tokens.error = Some(error);
+                None
+            }
+        })
+    }
+}
+
+// The content of an item starts after one to four spaces past its marker, or
+// after just one when more follow. A marker alone on its line takes it all.
+fn item_marker_width(marker: &str, spaces: &str) -> usize {
+    if spaces.len() > 4 && !spaces.ends_with('\n') {
+        return marker.len() + 1;
+    }
+    marker.len() + spaces.len()
+}
+
+// Only spaces and tabs are blank, along with the line ending. Other whitespace,
+// like a no-break space, is text.
+pub(crate) fn trim_blank_end(text: &str) -> &str {
+    text.trim_end_matches([' ', '\t', '\r', '\n'])
+}
+
+// Unicode punctuation and symbols, roughly: anything but letters, digits and
+// whitespace.
+fn is_punctuation(c: char) -> bool {
+    c.is_ascii_punctuation() || !(c.is_alphanumeric() || c.is_whitespace())
+}
+
+fn count_cells(row: &str) -> usize {
+    let row = row.trim();
+    let row = row.strip_prefix('|').unwrap_or(row);
+    let row = match row.strip_suffix('|') {
+        Some(stripped) if !stripped.ends_with('\\') => stripped,
+        _ => row,
+    };
+    row.split('|').filter(|cell| !cell.ends_with('\\')).count()
 }
 
 #[cfg(test)]
+#[allow(clippy::needless_borrow, clippy::useless_conversion)]
 mod test {
     use std::collections::VecDeque;
+    use std::ops::Range;
 
     use super::*;
     const HW: &str = "Hello World";
     const MT: &str = "Hello World _Italic HW_ Hello World, **Bold HW** blah blah blah ~~Strikethrough HW~~ blah blah blah ~~**_Hello World_**~~";
 
-    const L: fn(&str) -> Token = |s| Token::Literal(s.to_string());
-    const B: fn(&str) -> [Token; 3] = |s| [Token::Bold, L(s), Token::Bold];
-    const I: fn(&str) -> [Token; 3] = |s| [Token::Italic, L(s), Token::Italic];
-    const S: fn(&str) -> [Token; 3] = |s| [Token::Strikethrough, L(s), Token::Strikethrough];
-
-    const LV: fn(&str) -> VecDeque<Token> = |s| VecDeque::from([L(s)]);
-    const BV: fn(VecDeque<Token>) -> VecDeque<Token> = |t| surround(t, Token::Bold);
-    const IV: fn(VecDeque<Token>) -> VecDeque<Token> = |t| surround(t, Token::Italic);
-    const SV: fn(VecDeque<Token>) -> VecDeque<Token> = |t| surround(t, Token::Strikethrough);
+    const L: fn(&str) -> Token<'static> = |s| Token::Literal(s.to_string().into());
+    const D: fn(char, usize, bool, bool) -> Token<'static> =
+        |marker, length, open, close| Token::Delimiter {
+            marker,
+            length,
+            open,
+            close,
+        };
+    const B: fn(char, &str) -> [Token<'static>; 3] =
+        |m, s| [D(m, 2, true, false), L(s), D(m, 2, false, true)];
+    const I: fn(char, &str) -> [Token<'static>; 3] =
+        |m, s| [D(m, 1, true, false), L(s), D(m, 1, false, true)];
+    const S: fn(&str) -> [Token<'static>; 3] =
+        |s| [D('~', 2, true, false), L(s), D('~', 2, false, true)];
 
-    const SBIL: fn() -> VecDeque<Token> = || SV(BV(IV(LV(HW))));
+    // `~~**_Hello World_**~~`, where the `**` between punctuation can both
+    // open and close.
+    const SBIL: fn() -> VecDeque<Token<'static>> = || {
+        VecDeque::from([
+            D('~', 2, true, false),
+            D('*', 2, true, true),
+            D('_', 1, true, false),
+            L(HW),
+            D('_', 1, false, true),
+            D('*', 2, true, true),
+            D('~', 2, false, true),
+        ])
+    };
 
-    fn expect_multiple_tokens(start_token: Token) -> Vec<Token> {
+    fn expect_multiple_tokens(start_token: Token<'static>) -> Vec<Token<'static>> {
         let mut tokens = vec![start_token, L("Hello World ")];
-        tokens.extend_from_slice(&I("Italic HW"));
+        tokens.extend_from_slice(&I('_', "Italic HW"));
         tokens.push(L(" Hello World, "));
-        tokens.extend_from_slice(&B("Bold HW"));
+        tokens.extend_from_slice(&B('*', "Bold HW"));
         tokens.push(L(" blah blah blah "));
         tokens.extend_from_slice(&S("Strikethrough HW"));
         tokens.push(L(" blah blah blah "));
         build_expect_tokens(tokens, SBIL())
     }
 
-    fn surround(vec: VecDeque<Token>, token: Token) -> VecDeque<Token> {
-        let mut vec = vec;
-        vec.push_front(token.clone());
-        vec.push_back(token);
-        vec
-    }
-
-    fn build_expect_tokens(tokens: Vec<Token>, new_tokens: VecDeque<Token>) -> Vec<Token> {
+    fn build_expect_tokens(
+        tokens: Vec<Token<'static>>,
+        new_tokens: VecDeque<Token<'static>>,
+    ) -> Vec<Token<'static>> {
         let mut tokens = tokens;
         tokens.extend(new_tokens.into_iter());
         tokens
     }
 
-    fn assert_line(line: &str, expected_tokens: Vec<Token>) {
+    fn assert_line(line: &str, expected_tokens: Vec<Token<'static>>) {
         let mut tokenizer = Tokenizer::new();
-        tokenizer.set_line(&line.to_string());
+        tokenizer.set_line(line);
 
         for expected_token in expected_tokens {
             assert_eq!(tokenizer.next(), Some(expected_token));
@@ -225,17 +1267,10 @@ mod test {
         assert_eq!(tokenizer.next(), None);
     }
 
-    fn assert_block(lines: Vec<&str>, expected_tokens: Vec<Token>) {
-        let mut tokenizer = Tokenizer::new();
-        let mut tokens = expected_tokens.into_iter();
-
-        for line in lines {
-            tokenizer.set_line(&line.to_string());
-            while let Some(token) = tokenizer.next() {
-                assert_eq!(Some(token), tokens.next());
-            }
-        }
-        assert_eq!(tokenizer.next(), None);
+    fn assert_block(lines: Vec<&str>, expected_tokens: Vec<Token<'static>>) {
+        let input: String = lines.iter().map(|line| format!("{line}\n")).collect();
+        let tokens: Vec<Token> = tokens(&input).collect();
+        assert_eq!(tokens, expected_tokens);
     }
 
     #[test]
@@ -285,42 +1320,42 @@ mod test {
     fn header1_bold_star() {
         let line = "# **Hello World**";
         let mut expected_tokens = vec![Token::Header(1)];
-        expected_tokens.extend_from_slice(&B(HW));
+        expected_tokens.extend_from_slice(&B('*', HW));
         assert_line(line, expected_tokens);
     }
     #[test]
     fn header2_bold_star() {
         let line = "## **Hello World**";
         let mut expected_tokens = vec![Token::Header(2)];
-        expected_tokens.extend_from_slice(&B(HW));
+        expected_tokens.extend_from_slice(&B('*', HW));
         assert_line(line, expected_tokens);
     }
     #[test]
     fn header3_bold_star() {
         let line = "### **Hello World**";
         let mut expected_tokens = vec![Token::Header(3)];
-        expected_tokens.extend_from_slice(&B(HW));
+        expected_tokens.extend_from_slice(&B('*', HW));
         assert_line(line, expected_tokens);
     }
     #[test]
     fn header4_bold_star() {
         let line = "#### **Hello World**";
         let mut expected_tokens = vec![Token::Header(4)];
-        expected_tokens.extend_from_slice(&B(HW));
+        expected_tokens.extend_from_slice(&B('*', HW));
         assert_line(line, expected_tokens);
     }
     #[test]
     fn header5_bold_star() {
         let line = "##### **Hello World**";
         let mut expected_tokens = vec![Token::Header(5)];
-        expected_tokens.extend_from_slice(&B(HW));
+        expected_tokens.extend_from_slice(&B('*', HW));
         assert_line(line, expected_tokens);
     }
     #[test]
     fn header6_bold_underline() {
         let line = "###### __Hello World__";
         let mut expected_tokens = vec![Token::Header(6)];
-        expected_tokens.extend_from_slice(&B(HW));
+        expected_tokens.extend_from_slice(&B('_', HW));
         assert_line(&line, expected_tokens);
     }
 
@@ -328,42 +1363,42 @@ mod test {
     fn header1_italic_star() {
         let line = "# *Hello World*";
         let mut expected_tokens = vec![Token::Header(1)];
-        expected_tokens.extend_from_slice(&I(HW));
+        expected_tokens.extend_from_slice(&I('*', HW));
         assert_line(&line, expected_tokens);
     }
     #[test]
     fn header2_italic_star() {
         let line = "## *Hello World*";
         let mut expected_tokens = vec![Token::Header(2)];
-        expected_tokens.extend_from_slice(&I(HW));
+        expected_tokens.extend_from_slice(&I('*', HW));
         assert_line(&line, expected_tokens);
     }
     #[test]
     fn header3_italic_star() {
         let line = "### *Hello World*";
         let mut expected_tokens = vec![Token::Header(3)];
-        expected_tokens.extend_from_slice(&I(HW));
+        expected_tokens.extend_from_slice(&I('*', HW));
         assert_line(&line, expected_tokens);
     }
     #[test]
     fn header4_italic_star() {
         let line = "#### *Hello World*";
         let mut expected_tokens = vec![Token::Header(4)];
-        expected_tokens.extend_from_slice(&I(HW));
+        expected_tokens.extend_from_slice(&I('*', HW));
         assert_line(&line, expected_tokens);
     }
     #[test]
     fn header5_italic_star() {
         let line = "##### *Hello World*";
         let mut expected_tokens = vec![Token::Header(5)];
-        expected_tokens.extend_from_slice(&I(HW));
+        expected_tokens.extend_from_slice(&I('*', HW));
         assert_line(&line, expected_tokens);
     }
     #[test]
     fn header6_italic_star() {
         let line = "###### *Hello World*";
         let mut expected_tokens = vec![Token::Header(6)];
-        expected_tokens.extend_from_slice(&I(HW));
+        expected_tokens.extend_from_slice(&I('*', HW));
         assert_line(&line, expected_tokens);
     }
 
@@ -371,7 +1406,7 @@ mod test {
     fn header1_italic_underline() {
         let line = "# _Hello World_";
         let mut expected_tokens = vec![Token::Header(1)];
-        expected_tokens.extend_from_slice(&I(HW));
+        expected_tokens.extend_from_slice(&I('_', HW));
         assert_line(&line, expected_tokens);
     }
 
@@ -379,35 +1414,35 @@ mod test {
     fn header2_italic_underline() {
         let line = "## _Hello World_";
         let mut expected_tokens = vec![Token::Header(2)];
-        expected_tokens.extend_from_slice(&I(HW));
+        expected_tokens.extend_from_slice(&I('_', HW));
         assert_line(&line, expected_tokens);
     }
     #[test]
     fn header3_italic_underline() {
         let line = "### _Hello World_";
         let mut expected_tokens = vec![Token::Header(3)];
-        expected_tokens.extend_from_slice(&I(HW));
+        expected_tokens.extend_from_slice(&I('_', HW));
         assert_line(&line, expected_tokens);
     }
     #[test]
     fn header4_italic_underline() {
         let line = "#### _Hello World_";
         let mut expected_tokens = vec![Token::Header(4)];
-        expected_tokens.extend_from_slice(&I(HW));
+        expected_tokens.extend_from_slice(&I('_', HW));
         assert_line(&line, expected_tokens);
     }
     #[test]
     fn header5_italic_underline() {
         let line = "##### _Hello World_";
         let mut expected_tokens = vec![Token::Header(5)];
-        expected_tokens.extend_from_slice(&I(HW));
+        expected_tokens.extend_from_slice(&I('_', HW));
         assert_line(&line, expected_tokens);
     }
     #[test]
     fn header6_italic_underline() {
         let line = "###### _Hello World_";
         let mut expected_tokens = vec![Token::Header(6)];
-        expected_tokens.extend_from_slice(&I(HW));
+        expected_tokens.extend_from_slice(&I('_', HW));
         assert_line(&line, expected_tokens);
     }
 
@@ -529,32 +1564,234 @@ mod test {
         let expected_tokens = vec![Token::HorizontalRule];
         assert_line(&line, expected_tokens);
     }
+    #[test]
+    fn horizontal_rule_spaced() {
+        for line in ["- - -", "*****", "___   ", "* * * ", "-\t-\t-", "- --"] {
+            assert_line(line, vec![Token::HorizontalRule]);
+        }
+        assert_line("   ---", vec![Token::Indent(3), Token::HorizontalRule]);
+    }
+    #[test]
+    fn horizontal_rule_or_list() {
+        assert_line("- a -", vec![Token::UnorderedList('-'), L("a -")]);
+        let expected_tokens = vec![Token::UnorderedList('+'); 3];
+        assert_line("+ + +", expected_tokens);
+        assert_line("--", vec![Token::Paragraph, L("--")]);
+        let line = "- * * *";
+        let expected_tokens = vec![Token::UnorderedList('-'), Token::HorizontalRule];
+        assert_line(line, expected_tokens);
+    }
+    #[test]
+    fn horizontal_rule_or_underline() {
+        let lines = vec!["a", "- - -", "b", "***", "c", "----  "];
+        let expected_tokens = vec![
+            Token::Paragraph,
+            L("a"),
+            Token::HorizontalRule,
+            Token::Paragraph,
+            L("b"),
+            Token::HorizontalRule,
+            Token::Paragraph,
+            L("c"),
+            Token::Underline(2),
+        ];
+        assert_block(lines, expected_tokens);
+    }
+    #[test]
+    fn underline() {
+        let lines = vec!["a", "=  ", "- b", "  --", "> c", "---", "# d", "==="];
+        let expected_tokens = vec![
+            Token::Paragraph,
+            L("a"),
+            Token::Underline(1),
+            Token::UnorderedList('-'),
+            L("b"),
+            Token::Indent(2),
+            Token::Underline(2),
+            Token::Blockquote,
+            Token::Paragraph,
+            L("c"),
+            Token::HorizontalRule,
+            Token::Header(1),
+            L("d"),
+            Token::Paragraph,
+            L("==="),
+        ];
+        assert_block(lines, expected_tokens);
+    }
+
+    #[test]
+    fn blocks_after_list_marker() {
+        let expected_tokens = vec![Token::UnorderedList('-'), Token::Header(1), L("a")];
+        assert_line("- # a", expected_tokens);
+        let expected_tokens = vec![
+            Token::OrderedList(1, '.'),
+            Token::Blockquote,
+            Token::Paragraph,
+            L("b"),
+        ];
+        assert_line("1. > b", expected_tokens);
+    }
+
+    #[test]
+    fn list_marker_after_no_break_space() {
+        let line = "*\u{a0}a\u{a0}*";
+        assert_line(line, vec![Token::Paragraph, L(line)]);
+        assert_line("1.\u{a0}a", vec![Token::Paragraph, L("1.\u{a0}a")]);
+    }
 
     #[test]
     fn ulist_dash() {
         let line = "- Hello World";
-        let expected_tokens = vec![Token::UnorderedList, L(HW)];
+        let expected_tokens = vec![Token::UnorderedList('-'), L(HW)];
         assert_line(&line, expected_tokens);
     }
     #[test]
     fn ulist_plus() {
         let line = "+ Hello World";
-        let expected_tokens = vec![Token::UnorderedList, L(HW)];
+        let expected_tokens = vec![Token::UnorderedList('+'), L(HW)];
         assert_line(&line, expected_tokens);
     }
     #[test]
     fn ulist_star() {
         let line = "* Hello World";
-        let expected_tokens = vec![Token::UnorderedList, L(HW)];
+        let expected_tokens = vec![Token::UnorderedList('*'), L(HW)];
         assert_line(&line, expected_tokens);
     }
     #[test]
     fn ulist_strikethrough_bold_italic() {
         let line = "* ~~**_Hello World_**~~";
-        let expected_tokens = build_expect_tokens(vec![Token::UnorderedList], SBIL());
+        let expected_tokens = build_expect_tokens(vec![Token::UnorderedList('*')], SBIL());
         assert_line(&line, expected_tokens);
     }
 
+    #[test]
+    fn ulist_indented() {
+        let line = "  \t* Hello World";
+        let expected_tokens = vec![Token::Indent(4), Token::UnorderedList('*'), L(HW)];
+        assert_line(line, expected_tokens);
+    }
+    #[test]
+    fn olist_indented() {
+        let line = "    2. Hello World";
+        let expected_tokens = vec![Token::Indent(4), Token::OrderedList(2, '.'), L(HW)];
+        assert_line(line, expected_tokens);
+    }
+    #[test]
+    fn paragraph_indented() {
+        let line = "  resize in browser.";
+        let expected_tokens = vec![Token::Indent(2), Token::Paragraph, L("resize in browser.")];
+        assert_line(line, expected_tokens);
+    }
+    #[test]
+    fn paragraph_starting_with_modifier() {
+        let line = "**Hello World**";
+        let mut expected_tokens = vec![Token::Paragraph];
+        expected_tokens.extend_from_slice(&B('*', HW));
+        assert_line(line, expected_tokens);
+    }
+    #[test]
+    fn single_tilde() {
+        let line = "~ a ~b~ ~~c~~";
+        let mut expected_tokens = vec![Token::Paragraph, L("~ a ~b~ ")];
+        expected_tokens.extend_from_slice(&S("c"));
+        assert_line(line, expected_tokens);
+    }
+    #[test]
+    fn flanking_delimiters() {
+        let line = "snake_case_name 2 * 3 *x* a**b";
+        let mut expected_tokens = vec![Token::Paragraph, L("snake_case_name 2 * 3 ")];
+        expected_tokens.extend_from_slice(&I('*', "x"));
+        expected_tokens.extend([L(" a"), D('*', 2, true, true), L("b")]);
+        assert_line(line, expected_tokens);
+    }
+    #[test]
+    fn escapes() {
+        let line = "\\*a\\* \\[b] \\a\\";
+        let expected_tokens = vec![
+            Token::Paragraph,
+            L("*"),
+            L("a"),
+            L("*"),
+            L(" "),
+            L("["),
+            L("b] \\a\\"),
+        ];
+        assert_line(line, expected_tokens);
+    }
+    #[test]
+    fn block_escapes() {
+        assert_line("\\# a", vec![Token::Paragraph, L("#"), L(" a")]);
+        assert_line("\\- a", vec![Token::Paragraph, L("-"), L(" a")]);
+        assert_line("1\\. a", vec![Token::Paragraph, L("1"), L("."), L(" a")]);
+        assert_line("\\> a", vec![Token::Paragraph, L(">"), L(" a")]);
+        assert_line("\\---", vec![Token::Paragraph, L("-"), L("--")]);
+    }
+    #[test]
+    fn escapes_in_links() {
+        let line = "[a\\]](b\\)c \"d\\\"\")";
+        let expected_tokens = vec![
+            Token::Paragraph,
+            Token::Link {
+                destination: "b)c".into(),
+                title: Some("d\"".into()),
+            },
+            L("a"),
+            L("]"),
+            Token::LinkEnd,
+        ];
+        assert_line(line, expected_tokens);
+    }
+    #[test]
+    fn no_escapes_in_code() {
+        let line = "`\\*` \\`";
+        let expected_tokens = vec![
+            Token::Paragraph,
+            Token::CodeSpan("\\*".into()),
+            L(" "),
+            L("`"),
+        ];
+        assert_line(line, expected_tokens);
+        let lines = vec!["``` a\\+b", "\\*", "```"];
+        let expected_tokens = vec![
+            Token::CodeBlock("a+b".into()),
+            L("\\*"),
+            Token::CodeBlock("".into()),
+        ];
+        assert_block(lines, expected_tokens);
+    }
+    #[test]
+    fn whitespace_only() {
+        let line = " \t ";
+        let expected_tokens = vec![Token::Blank];
+        assert_line(line, expected_tokens);
+    }
+
+    #[test]
+    fn olist_dot() {
+        let line = "1. Hello World";
+        let expected_tokens = vec![Token::OrderedList(1, '.'), L(HW)];
+        assert_line(line, expected_tokens);
+    }
+    #[test]
+    fn olist_parenthesis() {
+        let line = "57) Hello World";
+        let expected_tokens = vec![Token::OrderedList(57, ')'), L(HW)];
+        assert_line(line, expected_tokens);
+    }
+    #[test]
+    fn olist_strikethrough_bold_italic() {
+        let line = "3. ~~**_Hello World_**~~";
+        let expected_tokens = build_expect_tokens(vec![Token::OrderedList(3, '.')], SBIL());
+        assert_line(line, expected_tokens);
+    }
+    #[test]
+    fn olist_without_space() {
+        let line = "2.Hello World";
+        let expected_tokens = vec![Token::Paragraph, L("2.Hello World")];
+        assert_line(line, expected_tokens);
+    }
+
     #[test]
     fn paragraph_multilple_tokens() {
         let expected_tokens = expect_multiple_tokens(Token::Paragraph);
@@ -577,12 +1814,488 @@ mod test {
 
         let lines = vec![line1, line2, line3, line4, line5];
         let expected_tokens = vec![
-            Token::CodeBlock("rust".to_string()),
-            Token::Literal("fn main() {".to_string()),
-            Token::Literal("    println!(\"Hello, world!\");".to_string()),
-            Token::Literal("}".to_string()),
-            Token::CodeBlock("".to_string()),
+            Token::CodeBlock("rust".into()),
+            Token::Literal("fn main() {".into()),
+            Token::Literal("    println!(\"Hello, world!\");".into()),
+            Token::Literal("}".into()),
+            Token::CodeBlock("".into()),
+        ];
+        assert_block(lines, expected_tokens);
+    }
+
+    #[test]
+    fn link() {
+        let line = "[link text](http://dev.nodeca.com)";
+        let expected_tokens = vec![
+            Token::Paragraph,
+            Token::Link {
+                destination: "http://dev.nodeca.com".into(),
+                title: None,
+            },
+            L("link text"),
+            Token::LinkEnd,
+        ];
+        assert_line(line, expected_tokens);
+    }
+
+    #[test]
+    fn link_with_title() {
+        let line = "see [link](http://a.com/(b) \"title text!\") here";
+        let expected_tokens = vec![
+            Token::Paragraph,
+            L("see "),
+            Token::Link {
+                destination: "http://a.com/(b)".into(),
+                title: Some("title text!".into()),
+            },
+            L("link"),
+            Token::LinkEnd,
+            L(" here"),
+        ];
+        assert_line(line, expected_tokens);
+    }
+
+    #[test]
+    fn link_inside_bold() {
+        let line = "- __[pica](https://nodeca.github.io/pica/demo/)__ - high quality";
+        let expected_tokens = vec![
+            Token::UnorderedList('-'),
+            D('_', 2, true, false),
+            Token::Link {
+                destination: "https://nodeca.github.io/pica/demo/".into(),
+                title: None,
+            },
+            L("pica"),
+            Token::LinkEnd,
+            D('_', 2, false, true),
+            L(" - high quality"),
+        ];
+        assert_line(line, expected_tokens);
+    }
+
+    #[test]
+    fn bold_inside_link() {
+        let line = "[**Hello World**](<a b>)";
+        let mut expected_tokens = vec![
+            Token::Paragraph,
+            Token::Link {
+                destination: "a b".into(),
+                title: None,
+            },
+        ];
+        expected_tokens.extend_from_slice(&B('*', HW));
+        expected_tokens.push(Token::LinkEnd);
+        assert_line(line, expected_tokens);
+    }
+
+    #[test]
+    fn image() {
+        let line = "![Stormtroopocat](https://octodex.github.com/images/stormtroopocat.jpg \"The Stormtroopocat\")";
+        let expected_tokens = vec![
+            Token::Paragraph,
+            Token::Image {
+                source: "https://octodex.github.com/images/stormtroopocat.jpg".into(),
+                title: Some("The Stormtroopocat".into()),
+            },
+            L("Stormtroopocat"),
+            Token::ImageEnd,
+        ];
+        assert_line(line, expected_tokens);
+    }
+
+    #[test]
+    fn not_a_link() {
+        let line = "[Alt text][id] and [x] (y) ! [z]";
+        let expected_tokens = vec![Token::Paragraph, L(line)];
+        assert_line(line, expected_tokens);
+    }
+
+    #[test]
+    fn unclosed_links() {
+        for unclosed in ["[a ", "[a](", "![a", "[a](b (", "[a](<b"] {
+            let line = unclosed.repeat(20000);
+            let expected_tokens = vec![Token::Paragraph, L(&line)];
+            assert_line(&line, expected_tokens);
+        }
+    }
+
+    #[test]
+    fn nested_links() {
+        let link = |destination: &'static str| Token::Link {
+            destination: destination.into(),
+            title: None,
+        };
+        let expected_tokens = vec![
+            Token::Paragraph,
+            L("["),
+            link("b"),
+            L("a"),
+            Token::LinkEnd,
+            L("](c)"),
+        ];
+        assert_line("[[a](b)](c)", expected_tokens);
+        // An image can hold a link, and a link an image.
+        let expected_tokens = vec![
+            Token::Paragraph,
+            link("c"),
+            Token::Image {
+                source: "b".into(),
+                title: None,
+            },
+            L("a"),
+            Token::ImageEnd,
+            Token::LinkEnd,
+        ];
+        assert_line("[![a](b)](c)", expected_tokens);
+        let line = format!("{}[a](b){}", "[".repeat(20000), "](c)".repeat(20000));
+        let mut expected_tokens = vec![Token::Paragraph, L(&line[..20000]), link("b")];
+        expected_tokens.extend([L("a"), Token::LinkEnd, L(&line[20006..])]);
+        assert_line(&line, expected_tokens);
+    }
+
+    #[test]
+    fn code_block_indented() {
+        let lines = vec!["  ```", "  `x`", "  ```"];
+        let expected_tokens = vec![
+            Token::Indent(2),
+            Token::CodeBlock("".into()),
+            Token::Literal("  `x`".into()),
+            Token::CodeBlock("".into()),
+        ];
+        assert_block(lines, expected_tokens);
+    }
+
+    #[test]
+    fn blockquote() {
+        let line = "> Hello World";
+        let expected_tokens = vec![Token::Blockquote, Token::Paragraph, L(HW)];
+        assert_line(line, expected_tokens);
+    }
+    #[test]
+    fn blockquote_nested() {
+        let line = ">> > Hello World";
+        let expected_tokens = vec![
+            Token::Blockquote,
+            Token::Blockquote,
+            Token::Blockquote,
+            Token::Paragraph,
+            L(HW),
+        ];
+        assert_line(line, expected_tokens);
+    }
+    #[test]
+    fn blockquote_header() {
+        let line = "> ## ~~**_Hello World_**~~";
+        let expected_tokens =
+            build_expect_tokens(vec![Token::Blockquote, Token::Header(2)], SBIL());
+        assert_line(line, expected_tokens);
+    }
+    #[test]
+    fn blockquote_ulist() {
+        let line = ">   - Hello World";
+        let expected_tokens = vec![
+            Token::Blockquote,
+            Token::Indent(2),
+            Token::UnorderedList('-'),
+            L(HW),
+        ];
+        assert_line(line, expected_tokens);
+    }
+    #[test]
+    fn blockquote_empty() {
+        let line = ">";
+        let expected_tokens = vec![Token::Blockquote, Token::Blank];
+        assert_line(line, expected_tokens);
+    }
+
+    #[test]
+    fn blockquote_code_block() {
+        let lines = vec!["> ```", "> > a", ">", "  > ```", "b"];
+        let expected_tokens = vec![
+            Token::Blockquote,
+            Token::CodeBlock("".into()),
+            Token::Blockquote,
+            L("> a"),
+            Token::Blockquote,
+            Token::Blockquote,
+            Token::CodeBlock("".into()),
+            Token::Paragraph,
+            L("b"),
+        ];
+        assert_block(lines, expected_tokens);
+    }
+
+    #[test]
+    fn blockquote_ends_code_block() {
+        let lines = vec!["> ```", "a", ""];
+        let expected_tokens = vec![
+            Token::Blockquote,
+            Token::CodeBlock("".into()),
+            Token::Paragraph,
+            L("a"),
+            Token::Blank,
+        ];
+        assert_block(lines, expected_tokens);
+    }
+
+    #[test]
+    fn code_span() {
+        let line = "Create a list with `+`, `-`, or `*`";
+        let expected_tokens = vec![
+            Token::Paragraph,
+            L("Create a list with "),
+            Token::CodeSpan("+".into()),
+            L(", "),
+            Token::CodeSpan("-".into()),
+            L(", or "),
+            Token::CodeSpan("*".into()),
+        ];
+        assert_line(line, expected_tokens);
+    }
+    #[test]
+    fn code_span_ignores_modifiers() {
+        let line = "**`_Hello World_`**";
+        let expected_tokens = vec![
+            Token::Paragraph,
+            D('*', 2, true, false),
+            Token::CodeSpan("_Hello World_".into()),
+            D('*', 2, false, true),
+        ];
+        assert_line(line, expected_tokens);
+    }
+    #[test]
+    fn code_span_double_backtick() {
+        let line = "a `` ` `` and ``b`c``";
+        let expected_tokens = vec![
+            Token::Paragraph,
+            L("a "),
+            Token::CodeSpan("`".into()),
+            L(" and "),
+            Token::CodeSpan("b`c".into()),
+        ];
+        assert_line(line, expected_tokens);
+    }
+    #[test]
+    fn code_span_unmatched() {
+        let line = "``a` b";
+        let expected_tokens = vec![Token::Paragraph, L(line)];
+        assert_line(line, expected_tokens);
+    }
+    #[test]
+    fn code_span_at_start() {
+        let line = "`code` and `  `";
+        let expected_tokens = vec![
+            Token::Paragraph,
+            Token::CodeSpan("code".into()),
+            L(" and "),
+            Token::CodeSpan("  ".into()),
+        ];
+        assert_line(line, expected_tokens);
+    }
+
+    #[test]
+    fn table() {
+        let lines = vec![
+            "| Option | Description |",
+            "| ------:| :---------: |",
+            "| data   | path to **data** files |",
+            "ext | a \\| b |",
+            "",
+            "| a |",
+        ];
+        let expected_tokens = vec![
+            Token::Paragraph,
+            L("| Option | Description |"),
+            Token::Table(vec![Alignment::Right, Alignment::Center]),
+            Token::TableRow,
+            L("Option"),
+            Token::TableCell,
+            L("Description"),
+            Token::TableRow,
+            L("data"),
+            Token::TableCell,
+            L("path to "),
+            D('*', 2, true, false),
+            L("data"),
+            D('*', 2, false, true),
+            L(" files"),
+            Token::TableRow,
+            L("ext"),
+            Token::TableCell,
+            L("a "),
+            L("|"),
+            L(" b"),
+            Token::Blank,
+            Token::Paragraph,
+            L("| a |"),
+        ];
+        assert_block(lines, expected_tokens);
+    }
+
+    #[test]
+    fn table_empty_cells() {
+        let lines = vec!["a | | c", ":- | - | -:", "| |"];
+        let expected_tokens = vec![
+            Token::Paragraph,
+            L("a | | c"),
+            Token::Table(vec![Alignment::Left, Alignment::None, Alignment::Right]),
+            Token::TableRow,
+            L("a"),
+            Token::TableCell,
+            Token::TableCell,
+            L("c"),
+            Token::TableRow,
+        ];
+        assert_block(lines, expected_tokens);
+    }
+
+    #[test]
+    fn table_column_mismatch() {
+        let lines = vec!["| a | b |", "| - |"];
+        let expected_tokens = vec![
+            Token::Paragraph,
+            L("| a | b |"),
+            Token::Paragraph,
+            L("| - |"),
         ];
         assert_block(lines, expected_tokens);
     }
+
+    #[test]
+    fn multibyte_characters() {
+        let line = "Olá **mundo** ~~çã~~ `€` [日本](/語 \"título\")";
+        let mut expected_tokens = vec![Token::Paragraph, L("Olá ")];
+        expected_tokens.extend(B('*', "mundo"));
+        expected_tokens.push(L(" "));
+        expected_tokens.extend(S("çã"));
+        expected_tokens.extend([
+            L(" "),
+            Token::CodeSpan("€".into()),
+            L(" "),
+            Token::Link {
+                destination: "/語".into(),
+                title: Some("título".into()),
+            },
+            L("日本"),
+            Token::LinkEnd,
+        ]);
+        assert_line(line, expected_tokens);
+    }
+
+    #[test]
+    fn multibyte_header() {
+        assert_line("#é", vec![Token::Paragraph, L("#é")]);
+        assert_line("# é", vec![Token::Header(1), L("é")]);
+    }
+
+    #[test]
+    fn long_line() {
+        let text = "ünïcödé ".repeat(20_000);
+        let line = format!("{text}**{}**", text.trim_end());
+        let mut expected_tokens = vec![Token::Paragraph, L(&text)];
+        expected_tokens.extend(B('*', text.trim_end()));
+        assert_line(&line, expected_tokens);
+    }
+
+    #[test]
+    fn document_tokens() {
+        let input = "# a\r\n\r\n- b\r\n";
+        let expected_tokens = vec![
+            Token::Header(1),
+            L("a"),
+            Token::Blank,
+            Token::UnorderedList('-'),
+            L("b"),
+        ];
+        assert_eq!(tokens(input).collect::<Vec<_>>(), expected_tokens);
+
+        let mut reader = Tokens::from_reader(input.as_bytes());
+        assert_eq!(reader.by_ref().collect::<Vec<_>>(), expected_tokens);
+        assert!(reader.error().is_none());
+    }
+
+    #[test]
+    fn reader_error() {
+        let mut tokens = Tokens::from_reader(&b"a\n\xff\nb"[..]);
+        assert_eq!(
+            tokens.by_ref().collect::<Vec<_>>(),
+            vec![Token::Paragraph, L("a")]
+        );
+        assert_eq!(
+            tokens.error().map(io::Error::kind),
+            Some(io::ErrorKind::InvalidData)
+        );
+    }
+
+    #[test]
+    fn spans() {
+        let input = "# é\r\n| a | b |\n|---|---|\n";
+        let spans: Vec<(Token<'static>, Range<usize>, usize, usize)> = tokens(input)
+            .spanned()
+            .map(|(token, span)| (token, span.range(), span.start.line, span.start.column))
+            .collect();
+        let expected_spans = vec![
+            (Token::Header(1), 0..2, 1, 1),
+            (L("é"), 2..4, 1, 3),
+            (Token::Paragraph, 6..6, 2, 1),
+            (L("| a | b |"), 6..15, 2, 1),
+            (Token::Table(vec![Alignment::None; 2]), 16..25, 3, 1),
+            (Token::TableRow, 6..8, 2, 1),
+            (L("a"), 8..9, 2, 3),
+            (Token::TableCell, 10..12, 2, 5),
+            (L("b"), 12..13, 2, 7),
+        ];
+        assert_eq!(spans, expected_spans);
+    }
+
+    #[test]
+    fn borrowed_tokens() {
+        let input = "a **b** `c` [d](e 'f')\n```g\n";
+        let borrowed = |token: &Token| match token {
+            Token::Literal(text) | Token::CodeSpan(text) | Token::CodeBlock(text) => {
+                matches!(text, Cow::Borrowed(_))
+            }
+            Token::Link { destination, title } => {
+                matches!(destination, Cow::Borrowed(_)) && matches!(title, Some(Cow::Borrowed(_)))
+            }
+            _ => true,
+        };
+        assert!(tokens(input).all(|token| borrowed(&token)));
+        assert_eq!(
+            Tokens::from_reader(input.as_bytes()).collect::<Vec<_>>(),
+            tokens(input).map(Token::into_owned).collect::<Vec<_>>()
+        );
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn trace_state_transitions() {
+        use std::cell::RefCell;
+
+        thread_local! {
+            static MESSAGES: RefCell<Vec<String>> = const { RefCell::new(vec![]) };
+        }
+        struct Logger;
+        impl log::Log for Logger {
+            fn enabled(&self, _: &log::Metadata) -> bool {
+                true
+            }
+            fn log(&self, record: &log::Record) {
+                MESSAGES.with_borrow_mut(|messages| messages.push(record.args().to_string()));
+            }
+            fn flush(&self) {}
+        }
+        log::set_logger(&Logger).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+
+        tokens("# a\n").for_each(drop);
+        let expected_messages = [
+            "line 1: \"# a\"",
+            "line 1, byte 0: End -> Start",
+            "line 1, byte 0: Start -> Process",
+            "line 1, byte 2: Process -> Text",
+            "line 1, byte 3: Text -> End",
+        ];
+        MESSAGES.with_borrow(|messages| assert_eq!(messages[..], expected_messages));
+    }
 }
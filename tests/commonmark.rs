@@ -0,0 +1,97 @@
+// Runs the CommonMark spec examples through the parser and the HTML renderer
+// and reports how many pass per section. The fixture is in the format of the
+// spec's `spec_tests.py --dump-tests` output and holds every example of spec
+// 0.31.2, numbered as in the spec.
+
+use serde_json::Value;
+
+// The examples known to pass, so that breaking one of them fails the test.
+// Any example can be in here, also one of an unsupported section that happens
+// to pass.
+const PASSING: &[u64] = &[
+    4, 9, 10, 11, 12, 13, 14, 15, 17, 22, 24, 28, 29, 30, 35, 42, 43, 44, 45, 46, 47, 49, 50, 51,
+    52, 53, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63, 64, 65, 66, 67, 68, 70, 74, 75, 76, 77, 78, 80,
+    81, 82, 83, 84, 86, 87, 88, 89, 90, 91, 92, 93, 94, 95, 96, 97, 98, 99, 101, 102, 103, 104,
+    105, 106, 108, 109, 113, 119, 121, 122, 126, 128, 129, 130, 131, 132, 133, 135, 136, 138, 140,
+    142, 145, 147, 197, 199, 209, 212, 213, 219, 220, 221, 222, 223, 224, 227, 228, 229, 230, 232,
+    233, 234, 235, 237, 238, 239, 240, 241, 242, 243, 244, 245, 246, 247, 248, 249, 250, 251, 255,
+    256, 258, 259, 260, 261, 262, 263, 265, 266, 267, 268, 269, 275, 276, 277, 279, 280, 281, 282,
+    283, 284, 285, 291, 292, 293, 294, 295, 296, 297, 298, 299, 300, 301, 302, 303, 304, 305, 306,
+    307, 310, 311, 312, 314, 315, 316, 318, 319, 320, 321, 322, 323, 324, 325, 326, 327, 328, 329,
+    330, 331, 332, 333, 334, 335, 336, 337, 338, 339, 340, 341, 342, 343, 345, 347, 348, 349, 350,
+    351, 352, 353, 354, 355, 356, 357, 358, 359, 360, 361, 362, 363, 364, 365, 366, 367, 368, 369,
+    370, 371, 372, 373, 374, 375, 376, 377, 378, 379, 380, 381, 382, 383, 384, 385, 386, 387, 388,
+    389, 390, 391, 392, 393, 394, 395, 396, 397, 398, 399, 400, 401, 402, 403, 404, 405, 406, 407,
+    408, 409, 410, 411, 412, 413, 414, 415, 416, 417, 418, 419, 420, 421, 422, 423, 424, 425, 426,
+    427, 428, 429, 430, 431, 432, 433, 434, 435, 436, 437, 438, 439, 440, 441, 442, 443, 444, 445,
+    446, 447, 448, 449, 450, 451, 452, 453, 454, 455, 456, 457, 458, 459, 460, 461, 462, 463, 464,
+    465, 466, 467, 468, 469, 470, 471, 472, 473, 474, 478, 479, 482, 483, 484, 485, 486, 487, 488,
+    490, 492, 493, 495, 496, 497, 498, 499, 500, 501, 505, 508, 509, 510, 511, 512, 513, 514, 515,
+    516, 517, 518, 519, 520, 521, 522, 523, 525, 546, 547, 548, 551, 552, 572, 574, 575, 578, 579,
+    580, 581, 590, 602, 606, 607, 608, 609, 610, 611, 612, 618, 619, 620, 621, 622, 624, 632, 640,
+    641, 644, 645, 646, 647, 648, 649, 650, 651, 652,
+];
+
+// Sections the parser doesn't implement, whose examples are expected to fail.
+const UNSUPPORTED: &[&str] = &[
+    "Tabs",
+    "Entity and numeric character references",
+    "Indented code blocks",
+    "HTML blocks",
+    "Link reference definitions",
+    "Autolinks",
+    "Raw HTML",
+    "Hard line breaks",
+];
+
+#[test]
+fn commonmark_spec() {
+    let examples: Vec<Value> = serde_json::from_str(include_str!("fixtures/spec.json")).unwrap();
+
+    let mut sections: Vec<(&str, usize, usize)> = vec![];
+    let mut regressions = vec![];
+    let mut fixed = vec![];
+    for example in &examples {
+        let number = example["example"].as_u64().unwrap();
+        let section = example["section"].as_str().unwrap();
+        let markdown = example["markdown"].as_str().unwrap();
+        let html = md_parser::html::render(&md_parser::parse(markdown));
+        let passed = html == example["html"].as_str().unwrap();
+
+        match sections.iter_mut().find(|(name, ..)| *name == section) {
+            Some((_, passes, total)) => {
+                *passes += passed as usize;
+                *total += 1;
+            }
+            None => sections.push((section, passed as usize, 1)),
+        }
+        match (passed, PASSING.contains(&number)) {
+            (false, true) => regressions.push(number),
+            (true, false) => fixed.push(number),
+            _ => {}
+        }
+    }
+
+    for (section, passes, total) in &sections {
+        let note = if UNSUPPORTED.contains(section) {
+            " (unsupported)"
+        } else {
+            ""
+        };
+        println!("{section:<40} {passes:>3}/{total}{note}");
+    }
+    let (passes, total) = sections
+        .iter()
+        .filter(|(section, ..)| !UNSUPPORTED.contains(section))
+        .fold((0, 0), |(passes, total), (_, p, t)| (passes + p, total + t));
+    println!("{:<40} {passes:>3}/{total}", "Supported");
+    let passes: usize = sections.iter().map(|(_, passes, _)| passes).sum();
+    println!("{:<40} {passes:>3}/{}", "Total", examples.len());
+    if !fixed.is_empty() {
+        println!("Passing now, add to `PASSING`: {fixed:?}");
+    }
+    assert!(
+        regressions.is_empty(),
+        "examples that passed fail now: {regressions:?}"
+    );
+}